@@ -5,7 +5,7 @@ extern crate libc;
     target_arch = "wasm32",
     not(any(target_env = "wasi", target_os = "wasi"))
 )))]
-use libc::{c_void, c_char, c_uint, size_t, c_int};
+use libc::{c_void, c_char, c_uint, c_ulonglong, size_t, c_int};
 
 #[cfg(all(
     target_arch = "wasm32",
@@ -17,7 +17,7 @@ extern crate std;
     target_arch = "wasm32",
     not(any(target_env = "wasi", target_os = "wasi"))
 ))]
-use std::os::raw::{c_void, c_char, c_uint, c_int};
+use std::os::raw::{c_void, c_char, c_uint, c_ulonglong, c_int};
 
 #[cfg(all(
     target_arch = "wasm32",
@@ -74,13 +74,30 @@ pub enum ContentChecksum {
     ChecksumEnabled,
 }
 
+#[derive(Clone, Debug)]
+#[repr(u32)]
+pub enum BlockChecksum {
+    NoBlockChecksum = 0,
+    BlockChecksumEnabled,
+}
+
+#[derive(Clone, Debug)]
+#[repr(u32)]
+pub enum FrameType {
+    Frame = 0,
+    SkippableFrame,
+}
+
 #[derive(Debug)]
 #[repr(C)]
 pub struct LZ4FFrameInfo {
     pub block_size_id: BlockSize,
     pub block_mode: BlockMode,
     pub content_checksum_flag: ContentChecksum,
-    pub reserved: [c_uint; 5],
+    pub frame_type: FrameType, // read-only field
+    pub content_size: c_ulonglong, // 0 == unknown
+    pub dict_id: c_uint, // 0 == no dictID provided
+    pub block_checksum_flag: BlockChecksum,
 }
 
 #[derive(Debug)]
@@ -109,14 +126,26 @@ pub struct LZ4FDecompressOptions {
     pub reserved: [c_uint; 3],
 }
 
+#[derive(Debug)]
+#[repr(C)]
+pub struct LZ4FCDict(c_void);
+
 #[derive(Debug)]
 #[repr(C)]
 pub struct LZ4StreamEncode(c_void);
 
+#[derive(Debug)]
+#[repr(C)]
+pub struct XXH32State(c_void);
+
 #[derive(Debug)]
 #[repr(C)]
 pub struct LZ4StreamDecode(c_void);
 
+#[derive(Debug)]
+#[repr(C)]
+pub struct LZ4StreamHC(c_void);
+
 pub const LZ4F_VERSION: c_uint = 100;
 
 extern "C" {
@@ -137,12 +166,59 @@ extern "C" {
     #[allow(non_snake_case)]
     pub fn LZ4_decompress_safe (source: *const c_char, dest: *mut c_char, compressedSize: c_int, maxDecompressedSize: c_int) -> c_int;
 
+    // int LZ4_decompress_safe_partial (const char* src, char* dst, int srcSize, int targetOutputSize, int dstCapacity);
+    #[allow(non_snake_case)]
+    pub fn LZ4_decompress_safe_partial (src: *const c_char, dst: *mut c_char, srcSize: c_int, targetOutputSize: c_int, dstCapacity: c_int) -> c_int;
+
+    // int LZ4_compress_destSize (const char* src, char* dst, int* srcSizePtr, int targetDstSize);
+    #[allow(non_snake_case)]
+    pub fn LZ4_compress_destSize (src: *const c_char, dst: *mut c_char, srcSizePtr: *mut c_int, targetDstSize: c_int) -> c_int;
+
+    // int LZ4_sizeofState(void);
+    pub fn LZ4_sizeofState() -> c_int;
+
+    // int LZ4_compress_fast_extState (void* state, const char* source, char* dest, int inputSize, int maxOutputSize, int acceleration);
+    // state must be aligned on 8 bytes.
+    #[allow(non_snake_case)]
+    pub fn LZ4_compress_fast_extState (state: *mut c_void, source: *const c_char, dest: *mut c_char, inputSize: c_int, maxOutputSize: c_int, acceleration: c_int) -> c_int;
+
+    // int LZ4_compress_fast_extState_fastReset (void* state, const char* src, char* dst, int srcSize, int dstCapacity, int acceleration);
+    // Skips the state initialization; only valid on a state initialized by a previous call to
+    // LZ4_compress_fast_extState().
+    #[allow(non_snake_case)]
+    pub fn LZ4_compress_fast_extState_fastReset (state: *mut c_void, src: *const c_char, dst: *mut c_char, srcSize: c_int, dstCapacity: c_int, acceleration: c_int) -> c_int;
+
+    // int LZ4_sizeofStateHC(void);
+    pub fn LZ4_sizeofStateHC() -> c_int;
+
+    // int LZ4_compress_HC_extStateHC(void* stateHC, const char* src, char* dst, int srcSize, int maxDstSize, int compressionLevel);
+    // stateHC must be aligned on 8 bytes.
+    #[allow(non_snake_case)]
+    pub fn LZ4_compress_HC_extStateHC (stateHC: *mut c_void, src: *const c_char, dst: *mut c_char, srcSize: c_int, maxDstSize: c_int, compressionLevel: c_int) -> c_int;
+
+    // int LZ4_compress_HC_extStateHC_fastReset (void* state, const char* src, char* dst, int srcSize, int dstCapacity, int compressionLevel);
+    // Skips the state initialization; only valid on a state initialized by a previous call to
+    // LZ4_compress_HC_extStateHC().
+    #[allow(non_snake_case)]
+    pub fn LZ4_compress_HC_extStateHC_fastReset (state: *mut c_void, src: *const c_char, dst: *mut c_char, srcSize: c_int, dstCapacity: c_int, compressionLevel: c_int) -> c_int;
+
+    // int LZ4_compress_HC_destSize(void* stateHC, const char* src, char* dst, int* srcSizePtr, int targetDstSize, int compressionLevel);
+    // Compresses as much of src as fits into targetDstSize bytes, and updates *srcSizePtr to the
+    // number of bytes read. stateHC must be aligned on 8 bytes.
+    #[allow(non_snake_case)]
+    pub fn LZ4_compress_HC_destSize (stateHC: *mut c_void, src: *const c_char, dst: *mut c_char, srcSizePtr: *mut c_int, targetDstSize: c_int, compressionLevel: c_int) -> c_int;
+
     // unsigned    LZ4F_isError(LZ4F_errorCode_t code);
     pub fn LZ4F_isError(code: size_t) -> c_uint;
 
     // const char* LZ4F_getErrorName(LZ4F_errorCode_t code);
     pub fn LZ4F_getErrorName(code: size_t) -> *const c_char;
 
+    // LZ4F_errorCodes LZ4F_getErrorCode(size_t functionResult);
+    // Returns the LZ4F_errorCodes enum value behind a function result, or 0 (OK_NoError)
+    // if the result is not an error.
+    pub fn LZ4F_getErrorCode(functionResult: size_t) -> c_uint;
+
     // LZ4F_createCompressionContext() :
     // The first thing to do is to create a compressionContext object, which will be used in all
     // compression operations.
@@ -259,6 +335,41 @@ extern "C" {
                             compressOptionsPtr: *const LZ4FCompressOptions)
                             -> LZ4FErrorCode;
 
+    // LZ4F_createCDict() :
+    // When compressing multiple messages / blocks with the same dictionary, it's recommended to
+    // load it just once. LZ4F_createCDict() will create a digested dictionary, ready to start
+    // future compression operations without startup delay.
+    // LZ4F_CDict can be created once and shared by multiple threads concurrently, since its
+    // usage is read-only.
+    // dictBuffer can be released after LZ4F_CDict creation, since its content is copied within
+    // CDict.
+    //
+    // LZ4F_CDict* LZ4F_createCDict(const void* dictBuffer, size_t dictSize);
+    pub fn LZ4F_createCDict(dictBuffer: *const u8, dictSize: size_t) -> *mut LZ4FCDict;
+
+    // void LZ4F_freeCDict(LZ4F_CDict* CDict);
+    pub fn LZ4F_freeCDict(CDict: *mut LZ4FCDict);
+
+    // LZ4F_compressBegin_usingCDict() :
+    // Inits streaming dictionary compression, and writes the frame header into dstBuffer.
+    // dstCapacity must be >= LZ4F_HEADER_SIZE_MAX bytes.
+    // prefsPtr is optional : you may provide NULL as argument, however, it's the only way to
+    // provide dictID in the frame header.
+    // cdict may be NULL, in which case no dictionary is used.
+    // The result of the function is the number of bytes written into dstBuffer for the header,
+    // or an error code (which can be tested using LZ4F_isError())
+    //
+    // size_t LZ4F_compressBegin_usingCDict(LZ4F_cctx* cctx,
+    //                                      void* dstBuffer, size_t dstCapacity,
+    //                                      const LZ4F_CDict* cdict,
+    //                                      const LZ4F_preferences_t* prefsPtr);
+    pub fn LZ4F_compressBegin_usingCDict(ctx: LZ4FCompressionContext,
+                                         dstBuffer: *mut u8,
+                                         dstCapacity: size_t,
+                                         cdict: *const LZ4FCDict,
+                                         prefsPtr: *const LZ4FPreferences)
+                                         -> LZ4FErrorCode;
+
     // LZ4F_createDecompressionContext() :
     // The first thing to do is to create a decompressionContext object, which will be used
     // in all decompression operations.
@@ -346,6 +457,44 @@ extern "C" {
                            optionsPtr: *const LZ4FDecompressOptions)
                            -> LZ4FErrorCode;
 
+    // LZ4F_decompress_usingDict() :
+    // Same as LZ4F_decompress(), using a predefined dictionary.
+    // Dictionary is used "in place", without any preprocessing.
+    // It must remain accessible throughout the entire frame decoding.
+    //
+    // size_t LZ4F_decompress_usingDict(LZ4F_dctx* dctxPtr,
+    //                                  void* dstBuffer, size_t* dstSizePtr,
+    //                                  const void* srcBuffer, size_t* srcSizePtr,
+    //                                  const void* dict, size_t dictSize,
+    //                                  const LZ4F_decompressOptions_t* decompressOptionsPtr);
+    pub fn LZ4F_decompress_usingDict(ctx: LZ4FDecompressionContext,
+                                     dstBuffer: *mut u8,
+                                     dstSizePtr: &mut size_t,
+                                     srcBuffer: *const u8,
+                                     srcSizePtr: &mut size_t,
+                                     dict: *const u8,
+                                     dictSize: size_t,
+                                     optionsPtr: *const LZ4FDecompressOptions)
+                                     -> LZ4FErrorCode;
+
+    // XXH32_hash_t XXH32(const void* input, size_t length, XXH32_hash_t seed);
+    pub fn XXH32(input: *const u8, length: size_t, seed: c_uint) -> c_uint;
+
+    // XXH32_state_t* XXH32_createState(void);
+    pub fn XXH32_createState() -> *mut XXH32State;
+
+    // XXH_errorcode XXH32_freeState(XXH32_state_t* statePtr);
+    pub fn XXH32_freeState(statePtr: *mut XXH32State) -> c_int;
+
+    // XXH_errorcode XXH32_reset(XXH32_state_t* statePtr, unsigned int seed);
+    pub fn XXH32_reset(statePtr: *mut XXH32State, seed: c_uint) -> c_int;
+
+    // XXH_errorcode XXH32_update(XXH32_state_t* statePtr, const void* input, size_t length);
+    pub fn XXH32_update(statePtr: *mut XXH32State, input: *const u8, length: size_t) -> c_int;
+
+    // XXH32_hash_t XXH32_digest(const XXH32_state_t* statePtr);
+    pub fn XXH32_digest(statePtr: *const XXH32State) -> c_uint;
+
     // int LZ4_versionNumber(void)
     pub fn LZ4_versionNumber() -> c_int;
 
@@ -365,6 +514,32 @@ extern "C" {
                                  input_size: c_int)
                                  -> c_int;
 
+    // int LZ4_compress_fast_continue(LZ4_stream_t* streamPtr,
+    //                                const char* src,
+    //                                char* dst,
+    //                                int srcSize,
+    //                                int dstCapacity,
+    //                                int acceleration)
+    pub fn LZ4_compress_fast_continue(LZ4_stream: *mut LZ4StreamEncode,
+                                      source: *const u8,
+                                      dest: *mut u8,
+                                      input_size: c_int,
+                                      dest_capacity: c_int,
+                                      acceleration: c_int)
+                                      -> c_int;
+
+    // int LZ4_saveDict(LZ4_stream_t* streamPtr, char* safeBuffer, int maxDictSize)
+    pub fn LZ4_saveDict(LZ4_stream: *mut LZ4StreamEncode,
+                        safe_buffer: *mut u8,
+                        max_dict_size: c_int)
+                        -> c_int;
+
+    // int LZ4_loadDict(LZ4_stream_t* streamPtr, const char* dictionary, int dictSize)
+    pub fn LZ4_loadDict(LZ4_stream: *mut LZ4StreamEncode,
+                        dictionary: *const u8,
+                        dict_size: c_int)
+                        -> c_int;
+
     // int LZ4_freeStream(LZ4_stream_t* LZ4_streamPtr)
     pub fn LZ4_freeStream(LZ4_stream: *mut LZ4StreamEncode) -> c_int;
 
@@ -383,9 +558,69 @@ extern "C" {
                                         max_decompressed_size: c_int)
                                         -> c_int;
 
+    // int LZ4_setStreamDecode(LZ4_streamDecode_t* LZ4_streamDecode,
+    //                         const char* dictionary,
+    //                         int dictSize)
+    pub fn LZ4_setStreamDecode(LZ4_stream: *mut LZ4StreamDecode,
+                               dictionary: *const u8,
+                               dict_size: c_int)
+                               -> c_int;
+
     // int LZ4_freeStreamDecode(LZ4_streamDecode_t* LZ4_stream)
     pub fn LZ4_freeStreamDecode(LZ4_stream: *mut LZ4StreamDecode) -> c_int;
 
+    // int LZ4_decompress_safe_usingDict(const char* src,
+    //                                   char* dst,
+    //                                   int srcSize,
+    //                                   int dstCapacity,
+    //                                   const char* dictStart,
+    //                                   int dictSize)
+    pub fn LZ4_decompress_safe_usingDict(source: *const u8,
+                                         dest: *mut u8,
+                                         compressed_size: c_int,
+                                         max_decompressed_size: c_int,
+                                         dictionary: *const u8,
+                                         dict_size: c_int)
+                                         -> c_int;
+
+    // LZ4_streamHC_t* LZ4_createStreamHC(void)
+    pub fn LZ4_createStreamHC() -> *mut LZ4StreamHC;
+
+    // void LZ4_setCompressionLevel(LZ4_streamHC_t* LZ4_streamHCPtr, int compressionLevel)
+    pub fn LZ4_setCompressionLevel(LZ4_stream: *mut LZ4StreamHC, compression_level: c_int);
+
+    // int LZ4_loadDictHC(LZ4_streamHC_t* streamHCPtr, const char* dictionary, int dictSize)
+    // Keeps the compression level set before.
+    pub fn LZ4_loadDictHC(LZ4_stream: *mut LZ4StreamHC,
+                          dictionary: *const u8,
+                          dict_size: c_int)
+                          -> c_int;
+
+    // int LZ4_compress_HC_continue(LZ4_streamHC_t* streamHCPtr,
+    //                              const char* src,
+    //                              char* dst,
+    //                              int srcSize,
+    //                              int maxDstSize)
+    pub fn LZ4_compress_HC_continue(LZ4_stream: *mut LZ4StreamHC,
+                                    source: *const u8,
+                                    dest: *mut u8,
+                                    input_size: c_int,
+                                    dest_capacity: c_int)
+                                    -> c_int;
+
+    // int LZ4_saveDictHC(LZ4_streamHC_t* streamHCPtr, char* safeBuffer, int maxDictSize)
+    pub fn LZ4_saveDictHC(LZ4_stream: *mut LZ4StreamHC,
+                          safe_buffer: *mut u8,
+                          max_dict_size: c_int)
+                          -> c_int;
+
+    // void LZ4_favorDecompressionSpeed(LZ4_streamHC_t* LZ4_streamHCPtr, int favor)
+    // Only has an effect at the optimal levels, 10 and up.
+    pub fn LZ4_favorDecompressionSpeed(LZ4_stream: *mut LZ4StreamHC, favor: c_int);
+
+    // int LZ4_freeStreamHC(LZ4_streamHC_t* streamHCPtr)
+    pub fn LZ4_freeStreamHC(LZ4_stream: *mut LZ4StreamHC) -> c_int;
+
     // LZ4F_resetDecompressionContext()
     // In case of an error, the context is left in "undefined" state.
     // In which case, it's necessary to reset it, before re-using it.
@@ -30,13 +30,15 @@ fn compress(src: &Path, dst: &Path) -> Result<()> {
     let mut fo = lz4::EncoderBuilder::new().build(File::create(dst)?)?;
     copy(&mut fi, &mut fo)?;
     match fo.finish() {
-        (_, result) => result,
+        (_, result) => result.map(|_| ()),
     }
 }
 
 fn decompress(src: &Path, dst: &Path) -> Result<()> {
     println!("Decompressing: {:?} -> {:?}", src, dst);
-    let mut fi = lz4::Decoder::new(File::open(src)?)?;
+    let mut fi = lz4::DecoderBuilder::new()
+        .multiple_frames(true)
+        .build(File::open(src)?)?;
     let mut fo = File::create(dst)?;
     copy(&mut fi, &mut fo)
 }
@@ -2,28 +2,42 @@
 //! It somehow resembles the [Python-lz4](http://python-lz4.readthedocs.io/en/stable/lz4.block.html) api,
 //! but using Rust's Option type, the function parameters have been a little simplified.
 //! As does python-lz4, this module supports prepending the compressed buffer with a u32 value
-//! representing the size of the original, uncompressed data.
+//! representing the size of the original, uncompressed data. Other formats of this size prefix
+//! are available through `SizePrefix`.
 //!
 //! # Examples
 //! ```
 //!
-//! use lz4::block::{compress,decompress};
+//! use lz4::block::{compress,decompress,SizePrefix};
 //!
 //! let v = vec![0u8; 1024];
 //!
-//! let comp_with_prefix = compress(&v, None, true).unwrap();
-//! let comp_wo_prefix = compress(&v, None, false).unwrap();
+//! let comp_with_prefix = compress(&v, None, SizePrefix::U32LE).unwrap();
+//! let comp_wo_prefix = compress(&v, None, SizePrefix::None).unwrap();
 //!
-//! assert_eq!(v, decompress(&comp_with_prefix, None).unwrap());
-//! assert_eq!(v, decompress(&comp_wo_prefix, Some(1024)).unwrap());
+//! assert_eq!(v, decompress(&comp_with_prefix, None, SizePrefix::U32LE).unwrap());
+//! assert_eq!(v, decompress(&comp_wo_prefix, Some(1024), SizePrefix::None).unwrap());
 //! ```
 
 use super::c_char;
+use super::error::Error;
 use super::liblz4::*;
-use std::io::{Error, ErrorKind, Result};
+use libc::c_void;
+use std::convert::TryInto;
+use std::io::Result;
+
+mod stream;
+
+pub use self::stream::{StreamCompressor, StreamCompressorHC, StreamDecompressor};
+
+/// Distance up to which a block may refer back into its history or dictionary.
+const WINDOW_SIZE: usize = 64 * 1024;
+
+/// Largest input liblz4 compresses into one block, `LZ4_MAX_INPUT_SIZE`.
+const MAX_INPUT_SIZE: usize = 0x7E00_0000;
 
 /// Represents the compression mode do be used.
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub enum CompressionMode {
     /// High compression with compression parameter
     HIGHCOMPRESSION(i32),
@@ -33,156 +47,900 @@ pub enum CompressionMode {
     DEFAULT,
 }
 
+/// Format of the uncompressed size that `compress` can prepend to the compressed data, and that
+/// `decompress` then reads back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SizePrefix {
+    /// No size prefix; the uncompressed size must be passed to `decompress`.
+    None,
+    /// 4-byte little-endian u32, as used by python-lz4.
+    U32LE,
+    /// 4-byte big-endian u32, as used by Cassandra and some Java codecs.
+    U32BE,
+    /// 8-byte little-endian u64.
+    U64LE,
+    /// 8-byte big-endian u64.
+    U64BE,
+    /// Unsigned LEB128 varint, 1 to 5 bytes long.
+    Varint,
+}
+
+impl SizePrefix {
+    /// Returns the largest number of bytes the prefix takes, to be added to `compress_bound` when
+    /// sizing buffers.
+    pub fn max_len(self) -> usize {
+        match self {
+            SizePrefix::None => 0,
+            SizePrefix::U32LE | SizePrefix::U32BE => 4,
+            SizePrefix::Varint => 5,
+            SizePrefix::U64LE | SizePrefix::U64BE => 8,
+        }
+    }
+
+    /// Returns the prefix for `size` in the first bytes of the array, and its length.
+    fn encode(self, size: usize) -> ([u8; 8], usize) {
+        let mut bytes = [0u8; 8];
+        let len = match self {
+            SizePrefix::None => 0,
+            SizePrefix::U32LE => {
+                bytes[..4].copy_from_slice(&(size as u32).to_le_bytes());
+                4
+            }
+            SizePrefix::U32BE => {
+                bytes[..4].copy_from_slice(&(size as u32).to_be_bytes());
+                4
+            }
+            SizePrefix::U64LE => {
+                bytes.copy_from_slice(&(size as u64).to_le_bytes());
+                8
+            }
+            SizePrefix::U64BE => {
+                bytes.copy_from_slice(&(size as u64).to_be_bytes());
+                8
+            }
+            SizePrefix::Varint => {
+                let mut size = size;
+                let mut len = 0;
+                while size >= 0x80 {
+                    bytes[len] = size as u8 | 0x80;
+                    size >>= 7;
+                    len += 1;
+                }
+                bytes[len] = size as u8;
+                len + 1
+            }
+        };
+        (bytes, len)
+    }
+
+    /// Writes the prefix for `size` at the start of `buffer`, and returns its length.
+    fn write(self, size: usize, buffer: &mut [u8]) -> Result<usize> {
+        let (bytes, len) = self.encode(size);
+        if buffer.len() < len {
+            return Err(Error::DstMaxSizeTooSmall.into());
+        }
+        buffer[..len].copy_from_slice(&bytes[..len]);
+        Ok(len)
+    }
+
+    /// Reads the prefix at the start of src, and returns the size with the prefix length.
+    fn read(self, src: &[u8]) -> Result<(u64, usize)> {
+        let len = match self {
+            SizePrefix::Varint => {
+                match src
+                    .iter()
+                    .take(self.max_len())
+                    .position(|byte| byte & 0x80 == 0)
+                {
+                    Some(last) => last + 1,
+                    None if src.len() < self.max_len() => {
+                        return Err(Error::SizePrefixMissing.into())
+                    }
+                    None => return Err(Error::UncompressedSizeInvalid.into()),
+                }
+            }
+            _ => self.max_len(),
+        };
+        if src.len() < len {
+            return Err(Error::SizePrefixMissing.into());
+        }
+        let size = match self {
+            SizePrefix::None => 0,
+            SizePrefix::U32LE => u32::from_le_bytes(src[..4].try_into().unwrap()) as u64,
+            SizePrefix::U32BE => u32::from_be_bytes(src[..4].try_into().unwrap()) as u64,
+            SizePrefix::U64LE => u64::from_le_bytes(src[..8].try_into().unwrap()),
+            SizePrefix::U64BE => u64::from_be_bytes(src[..8].try_into().unwrap()),
+            SizePrefix::Varint => src[..len].iter().enumerate().fold(0, |size, (i, byte)| {
+                size | ((byte & 0x7f) as u64) << (7 * i)
+            }),
+        };
+        Ok((size, len))
+    }
+}
+
+/// Returns the maximum size of the output of `compress` for `uncompressed_size` bytes of input,
+/// not counting the optional size prefix. Use it to size buffers for `compress_to_buffer`.
+///
+/// # Errors
+/// Returns `Error::SrcSizeTooLarge` if the input would be too long to compress.
+pub fn compress_bound(uncompressed_size: usize) -> Result<usize> {
+    // 0 iff src too large
+    let compress_bound: i32 = unsafe { LZ4_compressBound(uncompressed_size as i32) };
+
+    if uncompressed_size > (i32::MAX as usize) || compress_bound <= 0 {
+        return Err(Error::SrcSizeTooLarge.into());
+    }
+
+    Ok(compress_bound as usize)
+}
+
 /// Compresses the full src buffer using the specified CompressionMode, where None and Some(Default)
-/// are treated equally. If prepend_size is set, the source length will be prepended to the output
-/// buffer.
+/// are treated equally. Unless prefix is `SizePrefix::None`, the source length will be prepended to
+/// the output buffer in that format.
 ///
 ///
 /// # Errors
-/// Returns std::io::Error with ErrorKind::InvalidInput if the src buffer is too long.
-/// Returns std::io::Error with ErrorKind::Other if the compression failed inside the C library. If
+/// Returns `Error::SrcSizeTooLarge` if the src buffer is too long.
+/// Returns `Error::CompressionFailed` if the compression failed inside the C library. If
 /// this happens, the C api was not able to provide more information about the cause.
 ///
-pub fn compress(src: &[u8], mode: Option<CompressionMode>, prepend_size: bool) -> Result<Vec<u8>> {
-    // 0 iff src too large
-    let compress_bound: i32 = unsafe { LZ4_compressBound(src.len() as i32) };
-
-    if src.len() > (i32::max_value() as usize) || compress_bound <= 0 {
-        return Err(Error::new(
-            ErrorKind::InvalidInput,
-            "Compression input too long.",
-        ));
-    }
-
-    let mut compressed: Vec<u8> = vec![
-        0;
-        (if prepend_size {
-            compress_bound + 4
-        } else {
-            compress_bound
-        }) as usize
-    ];
+pub fn compress(src: &[u8], mode: Option<CompressionMode>, prefix: SizePrefix) -> Result<Vec<u8>> {
+    let compress_bound = compress_bound(src.len())?;
+    let mut compressed: Vec<u8> = vec![0; compress_bound + prefix.max_len()];
+
+    let size = compress_to_buffer(src, mode, prefix, &mut compressed)?;
+    compressed.truncate(size);
+    Ok(compressed)
+}
+
+/// Compresses the full src buffer into `buffer`, like `compress`, and returns the number of bytes
+/// written. A buffer of `compress_bound(src.len()) + prefix.max_len()` bytes is always large
+/// enough.
+///
+/// # Errors
+/// Returns `Error::SrcSizeTooLarge` if the src buffer is too long.
+/// Returns `Error::DstMaxSizeTooSmall` if the output does not fit in `buffer`.
+/// Returns `Error::CompressionFailed` if the compression failed inside the C library.
+///
+pub fn compress_to_buffer(
+    src: &[u8],
+    mode: Option<CompressionMode>,
+    prefix: SizePrefix,
+    buffer: &mut [u8],
+) -> Result<usize> {
+    compress_with(src, prefix, buffer, |src, dst| match mode {
+        Some(CompressionMode::HIGHCOMPRESSION(level)) => unsafe {
+            LZ4_compress_HC(
+                src.as_ptr() as *const c_char,
+                dst.as_mut_ptr() as *mut c_char,
+                src.len() as i32,
+                dst.len() as i32,
+                level,
+            )
+        },
+        Some(CompressionMode::FAST(accel)) => unsafe {
+            LZ4_compress_fast(
+                src.as_ptr() as *const c_char,
+                dst.as_mut_ptr() as *mut c_char,
+                src.len() as i32,
+                dst.len() as i32,
+                accel,
+            )
+        },
+        _ => unsafe {
+            LZ4_compress_default(
+                src.as_ptr() as *const c_char,
+                dst.as_mut_ptr() as *mut c_char,
+                src.len() as i32,
+                dst.len() as i32,
+            )
+        },
+    })
+}
+
+/// Compresses as much of the src buffer as fits into `buffer`, and returns the number of bytes
+/// read from src and written to `buffer`. Compressing the rest of src into the next buffer, and
+/// so on, fills fixed-size pages. The size prefix, if any, holds the number of bytes read, so
+/// that `decompress(&buffer[..written], None, prefix)` works. The acceleration of
+/// `CompressionMode::FAST` is ignored. With high compression, `BlockCompressor` avoids
+/// allocating a state for each page.
+///
+/// # Errors
+/// Returns `Error::DstMaxSizeTooSmall` if `buffer` cannot hold the size prefix and one byte.
+/// Returns `Error::CompressionFailed` if the compression failed inside the C library.
+///
+/// # Example
+/// ```
+/// use lz4::block::{compress_dest_size, decompress, SizePrefix};
+///
+/// let src: Vec<u8> = (0..20_000u32).flat_map(|i| (i % 251).to_le_bytes()).collect();
+/// let mut pages = Vec::new();
+/// let mut offset = 0;
+/// while offset < src.len() {
+///     let mut page = [0u8; 4096];
+///     let (read, written) =
+///         compress_dest_size(&src[offset..], None, SizePrefix::U32LE, &mut page).unwrap();
+///     offset += read;
+///     pages.push(page[..written].to_vec());
+/// }
+///
+/// let decompressed: Vec<u8> = pages
+///     .iter()
+///     .flat_map(|page| decompress(page, None, SizePrefix::U32LE).unwrap())
+///     .collect();
+/// assert_eq!(decompressed, src);
+/// ```
+pub fn compress_dest_size(
+    src: &[u8],
+    mode: Option<CompressionMode>,
+    prefix: SizePrefix,
+    buffer: &mut [u8],
+) -> Result<(usize, usize)> {
+    BlockCompressor::new().compress_dest_size(src, mode, prefix, buffer)
+}
+
+/// Compresses the full src buffer like `compress`, but lets it refer to `dict` as if it came
+/// right before. Only the last 64 KB of `dict` are used. Decompress the result with
+/// `decompress_with_dict` and the same dictionary.
+///
+/// # Errors
+/// Returns the errors of `compress`, and `Error::AllocationFailed` if the compression stream
+/// cannot be allocated.
+///
+/// # Example
+/// ```
+/// use lz4::block::{compress_with_dict, decompress_with_dict, SizePrefix};
+///
+/// let dict = b"{\"name\": \"\", \"email\": \"@example.com\", \"active\": true}";
+/// let record = b"{\"name\": \"alice\", \"email\": \"alice@example.com\", \"active\": true}";
+///
+/// let compressed = compress_with_dict(record, None, SizePrefix::U32LE, dict).unwrap();
+/// let decompressed = decompress_with_dict(&compressed, None, SizePrefix::U32LE, dict).unwrap();
+/// assert_eq!(decompressed, &record[..]);
+/// ```
+pub fn compress_with_dict(
+    src: &[u8],
+    mode: Option<CompressionMode>,
+    prefix: SizePrefix,
+    dict: &[u8],
+) -> Result<Vec<u8>> {
+    let compress_bound = compress_bound(src.len())?;
+    let mut compressed: Vec<u8> = vec![0; compress_bound + prefix.max_len()];
+    let dict = non_dangling(&dict[dict.len().saturating_sub(WINDOW_SIZE)..]);
+
+    let size = match mode {
+        Some(CompressionMode::HIGHCOMPRESSION(level)) => unsafe {
+            let stream = LZ4_createStreamHC();
+            if stream.is_null() {
+                return Err(Error::AllocationFailed.into());
+            }
+            LZ4_setCompressionLevel(stream, level);
+            LZ4_loadDictHC(stream, dict.as_ptr(), dict.len() as i32);
+            let result = compress_with(src, prefix, &mut compressed, |src, dst| {
+                LZ4_compress_HC_continue(
+                    stream,
+                    src.as_ptr(),
+                    dst.as_mut_ptr(),
+                    src.len() as i32,
+                    dst.len() as i32,
+                )
+            });
+            LZ4_freeStreamHC(stream);
+            result
+        },
+        _ => unsafe {
+            let acceleration = match mode {
+                Some(CompressionMode::FAST(accel)) => accel,
+                _ => 1,
+            };
+            let stream = LZ4_createStream();
+            if stream.is_null() {
+                return Err(Error::AllocationFailed.into());
+            }
+            LZ4_loadDict(stream, dict.as_ptr(), dict.len() as i32);
+            let result = compress_with(src, prefix, &mut compressed, |src, dst| {
+                LZ4_compress_fast_continue(
+                    stream,
+                    src.as_ptr(),
+                    dst.as_mut_ptr(),
+                    src.len() as i32,
+                    dst.len() as i32,
+                    acceleration,
+                )
+            });
+            LZ4_freeStream(stream);
+            result
+        },
+    }?;
+    compressed.truncate(size);
+    Ok(compressed)
+}
+
+/// Writes the optional size prefix, then compresses src into the rest of `buffer` with
+/// `compress`, which returns the compressed size or 0 on failure.
+fn compress_with<F>(src: &[u8], prefix: SizePrefix, buffer: &mut [u8], compress: F) -> Result<usize>
+where
+    F: FnOnce(&[u8], &mut [u8]) -> i32,
+{
+    let compress_bound = compress_bound(src.len())?;
+
+    let prefix_size = prefix.write(src.len(), buffer)?;
+    let dst_buf = &mut buffer[prefix_size..];
+    let capacity = dst_buf.len().min(i32::MAX as usize);
+    let src = non_dangling(src);
+
+    let dec_size = compress(src, &mut dst_buf[..capacity]);
+    if dec_size <= 0 {
+        // Compression only stops short for lack of room
+        if capacity < compress_bound {
+            return Err(Error::DstMaxSizeTooSmall.into());
+        }
+        return Err(Error::CompressionFailed.into());
+    }
+
+    Ok(dec_size as usize + prefix_size)
+}
+
+/// Which kind of compression the state of a `BlockCompressor` was last initialized for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum StateKind {
+    Fast,
+    High,
+}
 
-    let dec_size;
-    {
-        let dst_buf = if prepend_size {
-            let size = src.len() as u32;
-            compressed[0] = size as u8;
-            compressed[1] = (size >> 8) as u8;
-            compressed[2] = (size >> 16) as u8;
-            compressed[3] = (size >> 24) as u8;
-            &mut compressed[4..]
-        } else {
-            &mut compressed
+/// Compresses blocks like `compress`, but keeps the C library's compression state on the heap and
+/// reuses it across calls instead of initializing a new one every time. This pays off when
+/// compressing many small buffers.
+///
+/// # Examples
+/// ```
+/// use lz4::block::{decompress, BlockCompressor, CompressionMode, SizePrefix};
+///
+/// let mut compressor = BlockCompressor::new();
+/// for message in &[&b"first message"[..], b"second message"] {
+///     let compressed = compressor
+///         .compress(message, Some(CompressionMode::FAST(1)), SizePrefix::U32LE)
+///         .unwrap();
+///     assert_eq!(decompress(&compressed, None, SizePrefix::U32LE).unwrap(), *message);
+/// }
+/// ```
+#[derive(Debug)]
+pub struct BlockCompressor {
+    // u64 keeps the state 8-byte aligned, as the C library requires
+    state: Vec<u64>,
+    kind: Option<StateKind>,
+}
+
+impl Default for BlockCompressor {
+    fn default() -> Self {
+        BlockCompressor::new()
+    }
+}
+
+impl BlockCompressor {
+    /// Creates a compressor. Its state is allocated on first use, and reallocated only when
+    /// switching from fast to high compression.
+    pub fn new() -> BlockCompressor {
+        BlockCompressor {
+            state: Vec::new(),
+            kind: None,
+        }
+    }
+
+    /// Same as `block::compress`, reusing this compressor's state.
+    pub fn compress(
+        &mut self,
+        src: &[u8],
+        mode: Option<CompressionMode>,
+        prefix: SizePrefix,
+    ) -> Result<Vec<u8>> {
+        let compress_bound = compress_bound(src.len())?;
+        let mut compressed: Vec<u8> = vec![0; compress_bound + prefix.max_len()];
+
+        let size = self.compress_to_buffer(src, mode, prefix, &mut compressed)?;
+        compressed.truncate(size);
+        Ok(compressed)
+    }
+
+    /// Same as `block::compress_to_buffer`, reusing this compressor's state.
+    pub fn compress_to_buffer(
+        &mut self,
+        src: &[u8],
+        mode: Option<CompressionMode>,
+        prefix: SizePrefix,
+        buffer: &mut [u8],
+    ) -> Result<usize> {
+        let (kind, level) = match mode {
+            Some(CompressionMode::HIGHCOMPRESSION(level)) => (StateKind::High, level),
+            Some(CompressionMode::FAST(accel)) => (StateKind::Fast, accel),
+            _ => (StateKind::Fast, 1),
         };
+        let initialized = self.prepare(kind);
+        let state = self.state.as_mut_ptr() as *mut c_void;
+        compress_with(src, prefix, buffer, |src, dst| unsafe {
+            let src_ptr = src.as_ptr() as *const c_char;
+            let dst_ptr = dst.as_mut_ptr() as *mut c_char;
+            let (src_len, dst_len) = (src.len() as i32, dst.len() as i32);
+            match (kind, initialized) {
+                (StateKind::Fast, false) => {
+                    LZ4_compress_fast_extState(state, src_ptr, dst_ptr, src_len, dst_len, level)
+                }
+                (StateKind::Fast, true) => LZ4_compress_fast_extState_fastReset(
+                    state, src_ptr, dst_ptr, src_len, dst_len, level,
+                ),
+                (StateKind::High, false) => {
+                    LZ4_compress_HC_extStateHC(state, src_ptr, dst_ptr, src_len, dst_len, level)
+                }
+                (StateKind::High, true) => LZ4_compress_HC_extStateHC_fastReset(
+                    state, src_ptr, dst_ptr, src_len, dst_len, level,
+                ),
+            }
+        })
+    }
+
+    /// Same as `block::compress_dest_size`, reusing this compressor's state for high
+    /// compression.
+    pub fn compress_dest_size(
+        &mut self,
+        src: &[u8],
+        mode: Option<CompressionMode>,
+        prefix: SizePrefix,
+        buffer: &mut [u8],
+    ) -> Result<(usize, usize)> {
+        // Whatever does not fit is left for the next call anyway
+        let src = non_dangling(&src[..src.len().min(MAX_INPUT_SIZE)]);
+        // Room for the prefix of the largest size that may be read
+        let (_, prefix_size) = prefix.encode(src.len());
+        if buffer.len() <= prefix_size {
+            return Err(Error::DstMaxSizeTooSmall.into());
+        }
+        let dst = &mut buffer[prefix_size..];
+        let capacity = dst.len().min(i32::MAX as usize) as i32;
 
-        dec_size = match mode {
+        let mut read = src.len() as i32;
+        let written = match mode {
             Some(CompressionMode::HIGHCOMPRESSION(level)) => unsafe {
-                LZ4_compress_HC(
+                self.prepare(StateKind::High);
+                LZ4_compress_HC_destSize(
+                    self.state.as_mut_ptr() as *mut c_void,
                     src.as_ptr() as *const c_char,
-                    dst_buf.as_mut_ptr() as *mut c_char,
-                    src.len() as i32,
-                    compress_bound,
+                    dst.as_mut_ptr() as *mut c_char,
+                    &mut read,
+                    capacity,
                     level,
                 )
             },
-            Some(CompressionMode::FAST(accel)) => unsafe {
-                LZ4_compress_fast(
-                    src.as_ptr() as *const c_char,
-                    dst_buf.as_mut_ptr() as *mut c_char,
-                    src.len() as i32,
-                    compress_bound,
-                    accel,
-                )
-            },
             _ => unsafe {
-                LZ4_compress_default(
+                LZ4_compress_destSize(
                     src.as_ptr() as *const c_char,
-                    dst_buf.as_mut_ptr() as *mut c_char,
-                    src.len() as i32,
-                    compress_bound,
+                    dst.as_mut_ptr() as *mut c_char,
+                    &mut read,
+                    capacity,
                 )
             },
         };
-    }
-    if dec_size <= 0 {
-        return Err(Error::new(ErrorKind::Other, "Compression failed"));
+        if written <= 0 {
+            return Err(Error::CompressionFailed.into());
+        }
+
+        let (read, written) = (read as usize, written as usize);
+        let (bytes, len) = prefix.encode(read);
+        if len < prefix_size {
+            buffer.copy_within(prefix_size..prefix_size + written, len);
+        }
+        buffer[..len].copy_from_slice(&bytes[..len]);
+        Ok((read, written + len))
     }
 
-    compressed.truncate(if prepend_size { dec_size + 4 } else { dec_size } as usize);
-    Ok(compressed)
+    /// Makes the state large enough for `kind`. Returns whether it is already initialized for
+    /// it, in which case the next call may skip the initialization.
+    fn prepare(&mut self, kind: StateKind) -> bool {
+        let initialized = self.kind == Some(kind);
+        if !initialized {
+            let size = unsafe {
+                match kind {
+                    StateKind::Fast => LZ4_sizeofState(),
+                    StateKind::High => LZ4_sizeofStateHC(),
+                }
+            } as usize;
+            let words = size / 8 + 1;
+            if self.state.len() < words {
+                self.state = vec![0; words];
+            }
+            self.kind = Some(kind);
+        }
+        initialized
+    }
 }
 
-/// Decompresses the src buffer. If uncompressed_size is None, the source length will be read from
-/// the start of the input buffer.
+/// Decompresses the src buffer. Unless prefix is `SizePrefix::None`, the uncompressed size will
+/// be read from the start of the input buffer, and uncompressed_size, if given, is the largest
+/// size accepted. Without a prefix, uncompressed_size is required.
 ///
 ///
 /// # Errors
-/// Returns std::io::Error with ErrorKind::InvalidInput if the src buffer is too short, the
-/// provided (or parsed) uncompressed_size is to large or negative.
-/// Returns std::io::Error with ErrorKind::InvalidData if the decompression failed inside the C
+/// Returns `Error::SizePrefixMissing` if the src buffer is too short for the size prefix, and
+/// `Error::UncompressedSizeInvalid` if the provided (or parsed) uncompressed_size is missing, too
+/// large or negative.
+/// Returns `Error::DecompressionFailed` if the decompression failed inside the C
 /// library. This is most likely due to malformed input.
 ///
-pub fn decompress(mut src: &[u8], uncompressed_size: Option<i32>) -> Result<Vec<u8>> {
-    let size;
+pub fn decompress(
+    src: &[u8],
+    uncompressed_size: Option<i32>,
+    prefix: SizePrefix,
+) -> Result<Vec<u8>> {
+    let (size, _) = get_decompressed_size(src, uncompressed_size, prefix)?;
 
-    if let Some(s) = uncompressed_size {
-        size = s;
-    } else {
-        if src.len() < 4 {
-            return Err(Error::new(
-                ErrorKind::InvalidInput,
-                "Source buffer must at least contain size prefix.",
-            ));
-        }
-        size =
-            (src[0] as i32) | (src[1] as i32) << 8 | (src[2] as i32) << 16 | (src[3] as i32) << 24;
+    let mut decompressed = vec![0u8; size];
+    let dec_bytes = decompress_to_buffer(src, uncompressed_size, prefix, &mut decompressed)?;
+
+    decompressed.truncate(dec_bytes);
+    Ok(decompressed)
+}
+
+/// Decompresses the src buffer into `buffer`, like `decompress`, and returns the number of bytes
+/// written.
+///
+/// # Errors
+/// Returns the errors of `decompress`, and `Error::DstMaxSizeTooSmall` if the provided (or
+/// parsed) uncompressed_size is larger than `buffer`.
+///
+pub fn decompress_to_buffer(
+    src: &[u8],
+    uncompressed_size: Option<i32>,
+    prefix: SizePrefix,
+    buffer: &mut [u8],
+) -> Result<usize> {
+    decompress_with(src, uncompressed_size, prefix, buffer, |src, dst| unsafe {
+        LZ4_decompress_safe(
+            src.as_ptr() as *const c_char,
+            dst.as_mut_ptr() as *mut c_char,
+            src.len() as i32,
+            dst.len() as i32,
+        )
+    })
+}
+
+/// Skips the size prefix, then decompresses src into the start of `buffer` with `decompress`,
+/// which returns the decompressed size or a negative value on failure.
+fn decompress_with<F>(
+    src: &[u8],
+    uncompressed_size: Option<i32>,
+    prefix: SizePrefix,
+    buffer: &mut [u8],
+    decompress: F,
+) -> Result<usize>
+where
+    F: FnOnce(&[u8], &mut [u8]) -> i32,
+{
+    let (size, src) = get_decompressed_size(src, uncompressed_size, prefix)?;
 
-        src = &src[4..];
+    if size > buffer.len() {
+        return Err(Error::DstMaxSizeTooSmall.into());
     }
 
-    if size < 0 {
-        return Err(Error::new(
-            ErrorKind::InvalidInput,
-            if uncompressed_size.is_some() {
-                "Size parameter must not be negative."
-            } else {
-                "Parsed size prefix in buffer must not be negative."
-            },
-        ));
+    let dec_bytes = decompress(src, &mut buffer[..size]);
+    if dec_bytes < 0 {
+        return Err(Error::DecompressionFailed.into());
     }
 
-    if unsafe { LZ4_compressBound(size) } <= 0 {
-        return Err(Error::new(
-            ErrorKind::InvalidInput,
-            "Given size parameter is too big",
-        ));
+    Ok(dec_bytes as usize)
+}
+
+/// Returns how many bytes `decompress_in_place` needs past the end of the decompressed data, for
+/// a block of `compressed_len` bytes, as `LZ4_DECOMPRESS_INPLACE_MARGIN` in liblz4.
+pub fn decompress_in_place_margin(compressed_len: usize) -> usize {
+    (compressed_len >> 8) + 32
+}
+
+/// Returns the buffer size `decompress_in_place` needs for a block of `compressed_len` bytes
+/// decompressing to `decompressed_len` bytes. Reserving this capacity before reading the block
+/// into the buffer avoids any reallocation.
+pub fn decompress_in_place_buffer_size(compressed_len: usize, decompressed_len: usize) -> usize {
+    decompressed_len.max(compressed_len) + decompress_in_place_margin(compressed_len)
+}
+
+/// Decompresses the block held in the first `compressed_len` bytes of `buf` in place, leaving
+/// `buf` with the decompressed data. The block is moved to the end of a buffer of
+/// `decompress_in_place_buffer_size` bytes and decompressed to its start, so that the compressed
+/// and decompressed data never need to be in memory separately.
+///
+/// # Errors
+/// Returns the errors of `decompress`, and `Error::DstMaxSizeTooSmall` if `buf` is shorter than
+/// `compressed_len`. On error, the contents of `buf` are unspecified.
+///
+/// # Example
+/// ```
+/// use lz4::block::{compress, decompress_in_place, decompress_in_place_buffer_size, SizePrefix};
+///
+/// let data = b"in place, in place, in place, in place".repeat(100);
+/// let compressed = compress(&data, None, SizePrefix::None).unwrap();
+///
+/// let size = decompress_in_place_buffer_size(compressed.len(), data.len());
+/// let mut buf = Vec::with_capacity(size);
+/// buf.extend_from_slice(&compressed);
+/// decompress_in_place(&mut buf, compressed.len(), data.len()).unwrap();
+/// assert_eq!(buf, data);
+/// ```
+pub fn decompress_in_place(
+    buf: &mut Vec<u8>,
+    compressed_len: usize,
+    decompressed_len: usize,
+) -> Result<()> {
+    if buf.len() < compressed_len {
+        return Err(Error::DstMaxSizeTooSmall
+            .with_message("The buffer is shorter than the compressed block"));
+    }
+    if compressed_len > i32::MAX as usize {
+        return Err(Error::SrcSizeTooLarge.into());
+    }
+    if decompressed_len > i32::MAX as usize {
+        return Err(Error::UncompressedSizeInvalid.into());
     }
 
-    let mut decompressed = vec![0u8; size as usize];
+    let size = decompress_in_place_buffer_size(compressed_len, decompressed_len);
+    let start = size - compressed_len;
+    buf.truncate(compressed_len);
+    buf.resize(size, 0);
+    buf.copy_within(..compressed_len, start);
+
     let dec_bytes = unsafe {
+        // Both pointers come from the same one, as the regions overlap
+        let ptr = buf.as_mut_ptr();
         LZ4_decompress_safe(
+            ptr.add(start) as *const c_char,
+            ptr as *mut c_char,
+            compressed_len as i32,
+            decompressed_len as i32,
+        )
+    };
+    if dec_bytes < 0 {
+        return Err(Error::DecompressionFailed.into());
+    }
+
+    buf.truncate(dec_bytes as usize);
+    Ok(())
+}
+
+/// Decompresses only the first `target_len` bytes of the src buffer, stopping early instead of
+/// decoding the whole block. Without a size prefix, `capacity` is the uncompressed size, or an
+/// upper bound of it; otherwise the size is read from the prefix, as in `decompress`.
+///
+/// A src buffer cut short still decompresses, as long as it holds the compressed form of the
+/// first `target_len` bytes. Less than `target_len` bytes are returned if the block is shorter.
+///
+/// # Errors
+/// Returns the errors of `decompress`, and `Error::DecompressionFailed` if src is size-prefixed
+/// but ends before `target_len` bytes could be decoded.
+///
+/// # Example
+/// ```
+/// use lz4::block::{compress, decompress_partial, SizePrefix};
+///
+/// let record = b"header:v2;body:................................................";
+/// let compressed = compress(record, None, SizePrefix::Varint).unwrap();
+///
+/// let header = decompress_partial(&compressed, 9, None, SizePrefix::Varint).unwrap();
+/// assert_eq!(header, b"header:v2");
+/// ```
+pub fn decompress_partial(
+    src: &[u8],
+    target_len: usize,
+    capacity: Option<i32>,
+    prefix: SizePrefix,
+) -> Result<Vec<u8>> {
+    let (size, src) = get_decompressed_size(src, capacity, prefix)?;
+
+    let mut decompressed = vec![0u8; target_len.min(size)];
+    let dec_bytes = unsafe {
+        LZ4_decompress_safe_partial(
             src.as_ptr() as *const c_char,
             decompressed.as_mut_ptr() as *mut c_char,
             src.len() as i32,
-            size,
+            decompressed.len() as i32,
+            decompressed.len() as i32,
         )
     };
 
-    if dec_bytes < 0 {
-        return Err(Error::new(
-            ErrorKind::InvalidData,
-            "Decompression failed. Input invalid or too long?",
-        ));
+    // Only the size prefix tells a short block from a truncated one
+    if dec_bytes < 0 || (prefix != SizePrefix::None && (dec_bytes as usize) < decompressed.len()) {
+        return Err(Error::DecompressionFailed.into());
     }
 
     decompressed.truncate(dec_bytes as usize);
     Ok(decompressed)
 }
 
+/// Decompresses a block without size prefix whose uncompressed size is not known, as long as it is
+/// at most `max_size` bytes. The size is found by walking the sequences of the block first, which
+/// is much faster than decompressing it, so that the output is allocated only once.
+///
+/// # Errors
+/// Returns `Error::SizeLimitExceeded` if the block decompresses to more than `max_size` bytes.
+/// Returns `Error::DecompressionFailed` if the block is malformed.
+///
+/// # Example
+/// ```
+/// use lz4::block::{compress, decompress_unknown_size, SizePrefix};
+/// use lz4::Error;
+///
+/// let compressed = compress(&[1u8; 5000], None, SizePrefix::None).unwrap();
+///
+/// assert_eq!(decompress_unknown_size(&compressed, 10_000).unwrap(), vec![1u8; 5000]);
+/// let err = decompress_unknown_size(&compressed, 1000).unwrap_err();
+/// assert_eq!(Error::from(err), Error::SizeLimitExceeded);
+/// ```
+pub fn decompress_unknown_size(src: &[u8], max_size: usize) -> Result<Vec<u8>> {
+    let size = scan_decompressed_size(src, max_size.min(i32::MAX as usize))?;
+
+    let mut decompressed = vec![0u8; size];
+    let dec_bytes = unsafe {
+        LZ4_decompress_safe(
+            src.as_ptr() as *const c_char,
+            decompressed.as_mut_ptr() as *mut c_char,
+            src.len() as i32,
+            size as i32,
+        )
+    };
+    if dec_bytes as usize != size {
+        return Err(Error::DecompressionFailed.into());
+    }
+
+    Ok(decompressed)
+}
+
+/// Adds up the literal and match lengths of the sequences in the block src, giving up once they
+/// exceed `max_size`.
+fn scan_decompressed_size(src: &[u8], max_size: usize) -> Result<usize> {
+    // Reads a length continued by bytes of 255 after its 4-bit field in the token
+    fn length(src: &[u8], pos: &mut usize, field: u8) -> Option<usize> {
+        let mut length = field as usize;
+        if field == 15 {
+            loop {
+                let byte = *src.get(*pos)?;
+                *pos += 1;
+                length += byte as usize;
+                if byte != 255 {
+                    break;
+                }
+            }
+        }
+        Some(length)
+    }
+
+    let malformed = || Error::DecompressionFailed.into();
+    let mut pos = 0;
+    let mut size: usize = 0;
+    loop {
+        let token = *src.get(pos).ok_or_else(malformed)?;
+        pos += 1;
+        let literals = length(src, &mut pos, token >> 4).ok_or_else(malformed)?;
+        pos = pos.saturating_add(literals);
+        size = size.saturating_add(literals);
+        if size > max_size {
+            return Err(Error::SizeLimitExceeded.into());
+        }
+        // The last sequence has only literals
+        if pos >= src.len() {
+            return if pos == src.len() {
+                Ok(size)
+            } else {
+                Err(malformed())
+            };
+        }
+        // Skips the match offset
+        pos += 2;
+        let matched = length(src, &mut pos, token & 15).ok_or_else(malformed)?;
+        size = size.saturating_add(matched + 4);
+    }
+}
+
+/// Decompresses the output of `compress_with_dict`, given the same dictionary, like
+/// `decompress`.
+///
+/// # Errors
+/// Returns the errors of `decompress`. A wrong dictionary is not always detected, and may
+/// instead produce wrong data.
+///
+pub fn decompress_with_dict(
+    src: &[u8],
+    uncompressed_size: Option<i32>,
+    prefix: SizePrefix,
+    dict: &[u8],
+) -> Result<Vec<u8>> {
+    let (size, _) = get_decompressed_size(src, uncompressed_size, prefix)?;
+    let dict = &dict[dict.len().saturating_sub(WINDOW_SIZE)..];
+
+    let mut decompressed = vec![0u8; size];
+    let dec_bytes = decompress_with(
+        src,
+        uncompressed_size,
+        prefix,
+        &mut decompressed,
+        |src, dst| unsafe {
+            LZ4_decompress_safe_usingDict(
+                src.as_ptr(),
+                dst.as_mut_ptr(),
+                src.len() as i32,
+                dst.len() as i32,
+                dict.as_ptr(),
+                dict.len() as i32,
+            )
+        },
+    )?;
+
+    decompressed.truncate(dec_bytes);
+    Ok(decompressed)
+}
+
+/// Returns the uncompressed size, read from the size prefix unless it is `SizePrefix::None`, and
+/// the compressed block following the prefix.
+fn get_decompressed_size(
+    src: &[u8],
+    uncompressed_size: Option<i32>,
+    prefix: SizePrefix,
+) -> Result<(usize, &[u8])> {
+    if matches!(uncompressed_size, Some(size) if size < 0) {
+        return Err(Error::UncompressedSizeInvalid.into());
+    }
+    let (size, src) = match (prefix, uncompressed_size) {
+        (SizePrefix::None, Some(size)) => (size as i64, src),
+        (SizePrefix::None, None) => {
+            return Err(Error::UncompressedSizeInvalid
+                .with_message("The uncompressed size is required without a size prefix"))
+        }
+        (prefix, limit) => {
+            let (size, len) = prefix.read(src)?;
+            if let Some(limit) = limit {
+                if size > limit as u64 {
+                    return Err(Error::UncompressedSizeInvalid.with_message(format!(
+                        "Size prefix of {} bytes exceeds the limit of {} bytes",
+                        size, limit
+                    )));
+                }
+            }
+            (size.min(i64::MAX as u64) as i64, &src[len..])
+        }
+    };
+
+    if size < 0 || size > i32::MAX as i64 || unsafe { LZ4_compressBound(size as i32) } <= 0 {
+        return Err(Error::UncompressedSizeInvalid.into());
+    }
+
+    Ok((size as usize, src))
+}
+
+/// liblz4 offsets the source and dictionary pointers even when they are empty, so they must not
+/// be the dangling pointer of an empty slice.
+fn non_dangling(data: &[u8]) -> &[u8] {
+    if data.is_empty() {
+        &[0u8][..0]
+    } else {
+        data
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use crate::block::{compress, decompress, CompressionMode};
+    use crate::block::{
+        compress, compress_bound, compress_dest_size, compress_to_buffer, compress_with_dict,
+        decompress, decompress_in_place, decompress_in_place_buffer_size, decompress_partial,
+        decompress_to_buffer, decompress_unknown_size, decompress_with_dict, BlockCompressor,
+        CompressionMode, SizePrefix,
+    };
+    use crate::Error;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const PREFIXES: [SizePrefix; 6] = [
+        SizePrefix::None,
+        SizePrefix::U32LE,
+        SizePrefix::U32BE,
+        SizePrefix::U64LE,
+        SizePrefix::U64BE,
+        SizePrefix::Varint,
+    ];
 
     #[test]
     fn test_compression_without_prefix() {
@@ -193,7 +951,14 @@ mod test {
         }
         let mut v: Vec<Vec<u8>> = vec![];
         for i in 1..100 {
-            v.push(compress(&to_compress, Some(CompressionMode::FAST(i)), false).unwrap());
+            v.push(
+                compress(
+                    &to_compress,
+                    Some(CompressionMode::FAST(i)),
+                    SizePrefix::None,
+                )
+                .unwrap(),
+            );
         }
 
         // 12 is max high compression parameter
@@ -202,17 +967,17 @@ mod test {
                 compress(
                     &to_compress,
                     Some(CompressionMode::HIGHCOMPRESSION(i)),
-                    false,
+                    SizePrefix::None,
                 )
                 .unwrap(),
             );
         }
 
-        v.push(compress(&to_compress, None, false).unwrap());
+        v.push(compress(&to_compress, None, SizePrefix::None).unwrap());
 
         for val in v {
             assert_eq!(
-                decompress(&val, Some(to_compress.len() as i32)).unwrap(),
+                decompress(&val, Some(to_compress.len() as i32), SizePrefix::None).unwrap(),
                 to_compress
             );
         }
@@ -227,7 +992,14 @@ mod test {
         }
         let mut v: Vec<Vec<u8>> = vec![];
         for i in 1..100 {
-            v.push(compress(&to_compress, Some(CompressionMode::FAST(i)), true).unwrap());
+            v.push(
+                compress(
+                    &to_compress,
+                    Some(CompressionMode::FAST(i)),
+                    SizePrefix::U32LE,
+                )
+                .unwrap(),
+            );
         }
 
         // 12 is max high compression parameter
@@ -236,16 +1008,19 @@ mod test {
                 compress(
                     &to_compress,
                     Some(CompressionMode::HIGHCOMPRESSION(i)),
-                    true,
+                    SizePrefix::U32LE,
                 )
                 .unwrap(),
             );
         }
 
-        v.push(compress(&to_compress, None, true).unwrap());
+        v.push(compress(&to_compress, None, SizePrefix::U32LE).unwrap());
 
         for val in v {
-            assert_eq!(decompress(&val, None).unwrap(), to_compress);
+            assert_eq!(
+                decompress(&val, None, SizePrefix::U32LE).unwrap(),
+                to_compress
+            );
         }
     }
 
@@ -274,15 +1049,278 @@ mod test {
             reference += "this is a test string compressed by python-lz4 ";
         }
 
-        assert_eq!(decompress(&compressed, None).unwrap(), reference.as_bytes())
+        assert_eq!(
+            decompress(&compressed, None, SizePrefix::U32LE).unwrap(),
+            reference.as_bytes()
+        )
+    }
+
+    #[test]
+    fn test_compression_to_buffer() {
+        let src = b"this is a test string compressed into a reused buffer".repeat(20);
+        let mut compressed = vec![0u8; compress_bound(src.len()).unwrap() + 8];
+        let mut decompressed = vec![0u8; src.len()];
+        for &prefix in &PREFIXES {
+            let len = compress_to_buffer(&src, None, prefix, &mut compressed).unwrap();
+            assert_eq!(compressed[..len], compress(&src, None, prefix).unwrap()[..]);
+            let size = match prefix {
+                SizePrefix::None => Some(src.len() as i32),
+                _ => None,
+            };
+            let len =
+                decompress_to_buffer(&compressed[..len], size, prefix, &mut decompressed).unwrap();
+            assert_eq!(decompressed[..len], src[..]);
+        }
+
+        let err = compress_to_buffer(&src, None, SizePrefix::None, &mut [0u8; 8]).unwrap_err();
+        assert_eq!(Error::from(err), Error::DstMaxSizeTooSmall);
+        let err = decompress_to_buffer(
+            &compressed,
+            None,
+            SizePrefix::Varint,
+            &mut decompressed[..10],
+        )
+        .unwrap_err();
+        assert_eq!(Error::from(err), Error::DstMaxSizeTooSmall);
+    }
+
+    #[test]
+    fn test_size_prefix() {
+        let src = vec![7u8; 300];
+        let expected: [&[u8]; 6] = [
+            &[],
+            &[44, 1, 0, 0],
+            &[0, 0, 1, 44],
+            &[44, 1, 0, 0, 0, 0, 0, 0],
+            &[0, 0, 0, 0, 0, 0, 1, 44],
+            &[172, 2],
+        ];
+        for (&prefix, &expected) in PREFIXES.iter().zip(expected.iter()) {
+            let compressed = compress(&src, None, prefix).unwrap();
+            assert_eq!(&compressed[..expected.len()], expected);
+            let err = decompress(&compressed, Some(-1), prefix).unwrap_err();
+            assert_eq!(Error::from(err), Error::UncompressedSizeInvalid);
+            if prefix != SizePrefix::None {
+                assert_eq!(decompress(&compressed, None, prefix).unwrap(), src);
+                assert_eq!(decompress(&compressed, Some(300), prefix).unwrap(), src);
+                let err = decompress(&compressed, Some(299), prefix).unwrap_err();
+                assert_eq!(Error::from(err), Error::UncompressedSizeInvalid);
+                let err = decompress(&compressed[..expected.len() - 1], None, prefix).unwrap_err();
+                assert_eq!(Error::from(err), Error::SizePrefixMissing);
+            }
+        }
+
+        let err = decompress(&[0x80; 5], None, SizePrefix::Varint).unwrap_err();
+        assert_eq!(Error::from(err), Error::UncompressedSizeInvalid);
+        let err = decompress(&[0x80, 0, 0, 0], None, SizePrefix::U32BE).unwrap_err();
+        assert_eq!(Error::from(err), Error::UncompressedSizeInvalid);
+        let err = decompress(&[0], None, SizePrefix::None).unwrap_err();
+        assert_eq!(Error::from(err), Error::UncompressedSizeInvalid);
+    }
+
+    #[test]
+    fn test_block_compressor() {
+        let mut compressor = BlockCompressor::new();
+        let modes = [
+            None,
+            Some(CompressionMode::FAST(1)),
+            Some(CompressionMode::FAST(50)),
+            Some(CompressionMode::HIGHCOMPRESSION(9)),
+            Some(CompressionMode::HIGHCOMPRESSION(12)),
+            None,
+        ];
+        for (i, &mode) in modes.iter().cycle().take(60).enumerate() {
+            let src = format!("message {} of a stream of small RPC payloads", i).repeat(i % 7);
+            let compressed = compressor
+                .compress(src.as_bytes(), mode, SizePrefix::U32LE)
+                .unwrap();
+            assert_eq!(
+                decompress(&compressed, None, SizePrefix::U32LE).unwrap(),
+                src.as_bytes()
+            );
+        }
+    }
+
+    #[test]
+    fn test_compress_dest_size() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let src: Vec<u8> = (0..100_000)
+            .map(|_| b"abcdefgh"[rng.gen_range(0, 8)])
+            .collect();
+        let modes = [
+            (None, SizePrefix::U32LE),
+            (
+                Some(CompressionMode::HIGHCOMPRESSION(9)),
+                SizePrefix::Varint,
+            ),
+        ];
+        let mut compressor = BlockCompressor::new();
+        for &(mode, prefix) in &modes {
+            let mut decompressed = Vec::new();
+            let mut offset = 0;
+            while offset < src.len() {
+                let mut page = [0u8; 4096];
+                let (read, written) = compressor
+                    .compress_dest_size(&src[offset..], mode, prefix, &mut page)
+                    .unwrap();
+                assert!(read > 0 && written <= page.len());
+                let mut expected = [0u8; 4096];
+                assert_eq!(
+                    compress_dest_size(&src[offset..], mode, prefix, &mut expected).unwrap(),
+                    (read, written)
+                );
+                assert_eq!(page[..written], expected[..written]);
+                decompressed.extend(decompress(&page[..written], None, prefix).unwrap());
+                offset += read;
+            }
+            assert_eq!(decompressed, src);
+
+            let mut page = [0u8; 64];
+            let (read, written) =
+                compress_dest_size(b"short", mode, SizePrefix::None, &mut page).unwrap();
+            assert_eq!(read, 5);
+            assert_eq!(
+                decompress(&page[..written], Some(5), SizePrefix::None).unwrap(),
+                b"short"
+            );
+        }
+
+        let err = compress_dest_size(&src, None, SizePrefix::U32LE, &mut [0u8; 4]).unwrap_err();
+        assert_eq!(Error::from(err), Error::DstMaxSizeTooSmall);
+    }
+
+    #[test]
+    fn test_decompress_in_place() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let random: Vec<u8> = (0..100_000).map(|_| rng.gen()).collect();
+        let text = b"the quick brown fox jumps over the lazy dog ".repeat(5000);
+        for data in &[&random[..], &text[..], &text[..10], &[]] {
+            let compressed = compress(data, None, SizePrefix::None).unwrap();
+            let size = decompress_in_place_buffer_size(compressed.len(), data.len());
+            assert!(size < compressed.len() + data.len() || data.len() < 64);
+
+            let mut buf = Vec::with_capacity(size);
+            buf.extend_from_slice(&compressed);
+            let ptr = buf.as_ptr();
+            decompress_in_place(&mut buf, compressed.len(), data.len()).unwrap();
+            assert_eq!(&buf[..], *data);
+            assert_eq!(buf.as_ptr(), ptr);
+        }
+
+        let compressed = compress(&text, None, SizePrefix::None).unwrap();
+        let mut buf = compressed.clone();
+        buf.truncate(compressed.len() - 10);
+        let len = buf.len();
+        let err = decompress_in_place(&mut buf, len, text.len()).unwrap_err();
+        assert_eq!(Error::from(err), Error::DecompressionFailed);
+        let mut buf = compressed.clone();
+        let err = decompress_in_place(&mut buf, compressed.len() + 1, text.len()).unwrap_err();
+        assert_eq!(Error::from(err), Error::DstMaxSizeTooSmall);
+    }
+
+    #[test]
+    fn test_decompress_partial() {
+        let src: Vec<u8> = (0..5000)
+            .flat_map(|i| format!("record {};", i % 300).into_bytes())
+            .collect();
+        let with_prefix = compress(&src, None, SizePrefix::U32LE).unwrap();
+        let without_prefix = compress(&src, None, SizePrefix::None).unwrap();
+        let size = Some(src.len() as i32);
+
+        for &target_len in &[0, 1, 100, 10_000, src.len(), src.len() + 1] {
+            let expected = &src[..target_len.min(src.len())];
+            assert_eq!(
+                decompress_partial(&with_prefix, target_len, None, SizePrefix::U32LE).unwrap(),
+                expected
+            );
+            assert_eq!(
+                decompress_partial(&without_prefix, target_len, size, SizePrefix::None).unwrap(),
+                expected
+            );
+        }
+
+        // Truncated, but long enough for the first bytes
+        let half = &with_prefix[..with_prefix.len() / 2];
+        assert_eq!(
+            decompress_partial(half, 1000, None, SizePrefix::U32LE).unwrap(),
+            &src[..1000]
+        );
+        let err =
+            decompress_partial(&with_prefix[..14], 1000, None, SizePrefix::U32LE).unwrap_err();
+        assert_eq!(Error::from(err), Error::DecompressionFailed);
+    }
+
+    #[test]
+    fn test_decompress_unknown_size() {
+        let src: Vec<u8> = (0..20_000)
+            .flat_map(|i| format!("line {} of a log;", i % 1000).into_bytes())
+            .collect();
+        for &len in &[0, 1, 15, 16, 300, src.len()] {
+            let compressed = compress(&src[..len], None, SizePrefix::None).unwrap();
+            assert_eq!(
+                decompress_unknown_size(&compressed, len).unwrap(),
+                &src[..len]
+            );
+            if len > 0 {
+                let err = decompress_unknown_size(&compressed, len - 1).unwrap_err();
+                assert_eq!(Error::from(err), Error::SizeLimitExceeded);
+            }
+        }
+
+        let compressed = compress(&src, None, SizePrefix::None).unwrap();
+        for &len in &[0, 1, compressed.len() / 2, compressed.len() - 1] {
+            let err = decompress_unknown_size(&compressed[..len], usize::MAX).unwrap_err();
+            assert_eq!(Error::from(err), Error::DecompressionFailed);
+        }
+        let mut corrupted = compressed.clone();
+        corrupted.push(0);
+        assert!(decompress_unknown_size(&corrupted, usize::MAX).is_err());
+    }
+
+    #[test]
+    fn test_dict() {
+        let dict: Vec<u8> = (0..100)
+            .flat_map(|i| format!("key{:03}=value of record {};", i, i * 7).into_bytes())
+            .collect();
+        let src = b"key042=value of record 294;key043=value of record 301;";
+        let modes = [
+            None,
+            Some(CompressionMode::FAST(8)),
+            Some(CompressionMode::HIGHCOMPRESSION(9)),
+            Some(CompressionMode::HIGHCOMPRESSION(12)),
+        ];
+        for &mode in &modes {
+            let with_dict = compress_with_dict(src, mode, SizePrefix::U32LE, &dict).unwrap();
+            assert!(with_dict.len() < compress(src, mode, SizePrefix::U32LE).unwrap().len());
+            assert_eq!(
+                decompress_with_dict(&with_dict, None, SizePrefix::U32LE, &dict).unwrap(),
+                &src[..]
+            );
+            assert!(decompress(&with_dict, None, SizePrefix::U32LE).is_err());
+
+            let with_dict = compress_with_dict(src, mode, SizePrefix::None, &dict).unwrap();
+            let size = Some(src.len() as i32);
+            assert_eq!(
+                decompress_with_dict(&with_dict, size, SizePrefix::None, &dict).unwrap(),
+                &src[..]
+            );
+
+            let empty = compress_with_dict(&[], mode, SizePrefix::U32LE, &[]).unwrap();
+            assert!(decompress_with_dict(&empty, None, SizePrefix::U32LE, &[])
+                .unwrap()
+                .is_empty());
+        }
     }
 
     #[test]
     fn test_empty_compress() {
         use crate::block::{compress, decompress};
         let v = vec![0u8; 0];
-        let comp_with_prefix = compress(&v, None, true).unwrap();
+        let comp_with_prefix = compress(&v, None, SizePrefix::U32LE).unwrap();
         dbg!(&comp_with_prefix);
-        assert_eq!(v, decompress(&comp_with_prefix, None).unwrap());
+        assert_eq!(
+            v,
+            decompress(&comp_with_prefix, None, SizePrefix::U32LE).unwrap()
+        );
     }
 }
@@ -0,0 +1,420 @@
+use super::{
+    compress_bound, compress_with, decompress_with, get_decompressed_size, SizePrefix, WINDOW_SIZE,
+};
+use crate::error::Error;
+use crate::liblz4::*;
+use std::io::Result;
+
+/// Compresses a stream of messages, each into its own block, which may refer to the last 64 KB
+/// of the messages before it. This pays off for many small, similar messages, as sent by a chatty
+/// protocol, but the blocks can only be decompressed by a `StreamDecompressor` seeing them all
+/// in the same order.
+///
+/// The compressor keeps its own copy of the history, so the messages need not outlive the call.
+///
+/// # Example
+/// ```
+/// use lz4::block::{SizePrefix, StreamCompressor, StreamDecompressor};
+///
+/// let mut compressor = StreamCompressor::new().unwrap();
+/// let mut decompressor = StreamDecompressor::new().unwrap();
+/// for i in 0..10 {
+///     let message = format!("{{\"id\": {}, \"status\": \"ok\"}}", i);
+///     let compressed = compressor.compress(message.as_bytes(), SizePrefix::Varint).unwrap();
+///     let decompressed = decompressor.decompress(&compressed, None, SizePrefix::Varint).unwrap();
+///     assert_eq!(decompressed, message.as_bytes());
+/// }
+/// ```
+#[derive(Debug)]
+pub struct StreamCompressor {
+    stream: *mut LZ4StreamEncode,
+    history: History,
+}
+
+unsafe impl Send for StreamCompressor {}
+
+/// Same as `StreamCompressor`, with the high compression of `CompressionMode::HIGHCOMPRESSION`.
+/// Its blocks are decompressed by a `StreamDecompressor` too.
+#[derive(Debug)]
+pub struct StreamCompressorHC {
+    stream: *mut LZ4StreamHC,
+    history: History,
+}
+
+unsafe impl Send for StreamCompressorHC {}
+
+/// Decompresses the blocks of a `StreamCompressor` or `StreamCompressorHC`, which must be passed in the order they were
+/// compressed. A block that fails to decompress does not change the history, but the stream
+/// is out of sync if it was not the one expected.
+#[derive(Debug)]
+pub struct StreamDecompressor {
+    stream: *mut LZ4StreamDecode,
+    // The last 64 KB of output, followed by room to append more.
+    history: Vec<u8>,
+    end: usize,
+}
+
+unsafe impl Send for StreamDecompressor {}
+
+impl StreamCompressor {
+    pub fn new() -> Result<StreamCompressor> {
+        let stream = unsafe { LZ4_createStream() };
+        if stream.is_null() {
+            return Err(Error::AllocationFailed.into());
+        }
+        Ok(StreamCompressor {
+            stream,
+            history: History::new(),
+        })
+    }
+
+    /// Compresses the next message, like `block::compress` with the default mode.
+    pub fn compress(&mut self, src: &[u8], prefix: SizePrefix) -> Result<Vec<u8>> {
+        let compress_bound = compress_bound(src.len())?;
+        let mut compressed: Vec<u8> = vec![0; compress_bound + prefix.max_len()];
+
+        let size = self.compress_to_buffer(src, prefix, &mut compressed)?;
+        compressed.truncate(size);
+        Ok(compressed)
+    }
+
+    /// Compresses the next message into `buffer`, like `block::compress_to_buffer`.
+    ///
+    /// # Errors
+    /// Unlike `block::compress_to_buffer`, returns `Error::DstMaxSizeTooSmall` whenever
+    /// `buffer` is shorter than `compress_bound(src.len()) + prefix.max_len()`, as
+    /// liblz4 cannot resume the stream after running out of room.
+    pub fn compress_to_buffer(
+        &mut self,
+        src: &[u8],
+        prefix: SizePrefix,
+        buffer: &mut [u8],
+    ) -> Result<usize> {
+        let stream = self.stream;
+        self.history.compress(
+            src,
+            prefix,
+            buffer,
+            |src, dst| unsafe {
+                LZ4_compress_fast_continue(
+                    stream,
+                    src.as_ptr(),
+                    dst.as_mut_ptr(),
+                    src.len() as i32,
+                    dst.len() as i32,
+                    1,
+                )
+            },
+            |history| unsafe { LZ4_saveDict(stream, history.as_mut_ptr(), WINDOW_SIZE as i32) },
+        )
+    }
+}
+
+impl Drop for StreamCompressor {
+    fn drop(&mut self) {
+        unsafe { LZ4_freeStream(self.stream) };
+    }
+}
+
+impl StreamCompressorHC {
+    /// Creates a compressor using the given compression level, as in
+    /// `CompressionMode::HIGHCOMPRESSION`.
+    pub fn new(level: i32) -> Result<StreamCompressorHC> {
+        let stream = unsafe { LZ4_createStreamHC() };
+        if stream.is_null() {
+            return Err(Error::AllocationFailed.into());
+        }
+        unsafe { LZ4_setCompressionLevel(stream, level) };
+        Ok(StreamCompressorHC {
+            stream,
+            history: History::new(),
+        })
+    }
+
+    /// Changes the compression level from the next message on, keeping the history.
+    pub fn set_level(&mut self, level: i32) {
+        unsafe { LZ4_setCompressionLevel(self.stream, level) };
+    }
+
+    /// Makes levels 10 and up favor faster decompression over a slightly better ratio.
+    pub fn favor_decompression_speed(&mut self, favor: bool) {
+        unsafe { LZ4_favorDecompressionSpeed(self.stream, favor as i32) };
+    }
+
+    /// Compresses the next message, like `block::compress` in high compression mode.
+    pub fn compress(&mut self, src: &[u8], prefix: SizePrefix) -> Result<Vec<u8>> {
+        let compress_bound = compress_bound(src.len())?;
+        let mut compressed: Vec<u8> = vec![0; compress_bound + prefix.max_len()];
+
+        let size = self.compress_to_buffer(src, prefix, &mut compressed)?;
+        compressed.truncate(size);
+        Ok(compressed)
+    }
+
+    /// Compresses the next message into `buffer`, like `StreamCompressor::compress_to_buffer`.
+    pub fn compress_to_buffer(
+        &mut self,
+        src: &[u8],
+        prefix: SizePrefix,
+        buffer: &mut [u8],
+    ) -> Result<usize> {
+        let stream = self.stream;
+        self.history.compress(
+            src,
+            prefix,
+            buffer,
+            |src, dst| unsafe {
+                LZ4_compress_HC_continue(
+                    stream,
+                    src.as_ptr(),
+                    dst.as_mut_ptr(),
+                    src.len() as i32,
+                    dst.len() as i32,
+                )
+            },
+            |history| unsafe { LZ4_saveDictHC(stream, history.as_mut_ptr(), WINDOW_SIZE as i32) },
+        )
+    }
+}
+
+impl Drop for StreamCompressorHC {
+    fn drop(&mut self) {
+        unsafe { LZ4_freeStreamHC(self.stream) };
+    }
+}
+
+/// The input history of a compression stream.
+#[derive(Debug)]
+struct History {
+    // Room for the 64 KB window followed by a message up to 64 KB, so that
+    // small messages are compressed right after their history.
+    buffer: Vec<u8>,
+    end: usize,
+}
+
+impl History {
+    fn new() -> History {
+        History {
+            buffer: vec![0; 2 * WINDOW_SIZE],
+            end: 0,
+        }
+    }
+
+    /// Compresses the next message with `compress`, which continues the stream. `save_dict`
+    /// moves the end of the stream's history to the start of the given buffer, and returns
+    /// its size.
+    fn compress<C, S>(
+        &mut self,
+        src: &[u8],
+        prefix: SizePrefix,
+        buffer: &mut [u8],
+        compress: C,
+        mut save_dict: S,
+    ) -> Result<usize>
+    where
+        C: FnOnce(&[u8], &mut [u8]) -> i32,
+        S: FnMut(&mut [u8]) -> i32,
+    {
+        if buffer.len() < compress_bound(src.len())? + prefix.max_len() {
+            return Err(Error::DstMaxSizeTooSmall.into());
+        }
+
+        // Small messages are copied after the history, moving the last 64 KB
+        // of it to the front when full, so that all of it stays a prefix.
+        let copied = src.len() <= WINDOW_SIZE;
+        let src = if copied {
+            if self.end + src.len() > self.buffer.len() {
+                self.end = save_dict(&mut self.buffer) as usize;
+            }
+            self.buffer[self.end..self.end + src.len()].copy_from_slice(src);
+            &self.buffer[self.end..self.end + src.len()]
+        } else {
+            src
+        };
+
+        let len = compress_with(src, prefix, buffer, compress)?;
+
+        if copied {
+            self.end += src.len();
+        } else {
+            // The stream refers to src, which the caller may change or free.
+            self.end = save_dict(&mut self.buffer) as usize;
+        }
+        Ok(len)
+    }
+}
+
+impl StreamDecompressor {
+    pub fn new() -> Result<StreamDecompressor> {
+        let stream = unsafe { LZ4_createStreamDecode() };
+        if stream.is_null() {
+            return Err(Error::AllocationFailed.into());
+        }
+        Ok(StreamDecompressor {
+            stream,
+            history: vec![0; 2 * WINDOW_SIZE],
+            end: 0,
+        })
+    }
+
+    /// Decompresses the next block, like `block::decompress`.
+    pub fn decompress(
+        &mut self,
+        src: &[u8],
+        uncompressed_size: Option<i32>,
+        prefix: SizePrefix,
+    ) -> Result<Vec<u8>> {
+        let (size, _) = get_decompressed_size(src, uncompressed_size, prefix)?;
+
+        let mut decompressed = vec![0u8; size];
+        let dec_bytes =
+            self.decompress_to_buffer(src, uncompressed_size, prefix, &mut decompressed)?;
+
+        decompressed.truncate(dec_bytes);
+        Ok(decompressed)
+    }
+
+    /// Decompresses the next block into `buffer`, like `block::decompress_to_buffer`.
+    pub fn decompress_to_buffer(
+        &mut self,
+        src: &[u8],
+        uncompressed_size: Option<i32>,
+        prefix: SizePrefix,
+        buffer: &mut [u8],
+    ) -> Result<usize> {
+        let stream = self.stream;
+        let len = decompress_with(src, uncompressed_size, prefix, buffer, |src, dst| unsafe {
+            LZ4_decompress_safe_continue(
+                stream,
+                src.as_ptr(),
+                dst.as_mut_ptr(),
+                src.len() as i32,
+                dst.len() as i32,
+            )
+        })?;
+        self.remember(&buffer[..len]);
+        Ok(len)
+    }
+
+    /// Appends `output` to the history and points the stream at its last 64 KB,
+    /// as the caller's buffer may change before the next block.
+    fn remember(&mut self, output: &[u8]) {
+        if output.len() >= WINDOW_SIZE {
+            self.history[..WINDOW_SIZE].copy_from_slice(&output[output.len() - WINDOW_SIZE..]);
+            self.end = WINDOW_SIZE;
+        } else {
+            if self.end + output.len() > self.history.len() {
+                self.history
+                    .copy_within(self.end - WINDOW_SIZE..self.end, 0);
+                self.end = WINDOW_SIZE;
+            }
+            self.history[self.end..self.end + output.len()].copy_from_slice(output);
+            self.end += output.len();
+        }
+        let start = self.end.saturating_sub(WINDOW_SIZE);
+        unsafe {
+            LZ4_setStreamDecode(
+                self.stream,
+                self.history[start..].as_ptr(),
+                (self.end - start) as i32,
+            )
+        };
+    }
+}
+
+impl Drop for StreamDecompressor {
+    fn drop(&mut self) {
+        unsafe { LZ4_freeStreamDecode(self.stream) };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{StreamCompressor, StreamCompressorHC, StreamDecompressor};
+    use crate::block::{compress, decompress, SizePrefix};
+    use crate::Error;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn message(i: usize) -> Vec<u8> {
+        let mut message = format!(
+            "{{\"seq\": {}, \"user\": \"user{}\", \"ok\": true}}",
+            i,
+            i % 13
+        )
+        .repeat(i % 5)
+        .into_bytes();
+        if i % 17 == 3 {
+            // Larger than the window, and barely compressible
+            let mut rng = StdRng::seed_from_u64(i as u64);
+            message.extend((0..100_000).map(|_| rng.gen::<u8>()));
+        }
+        message
+    }
+
+    #[test]
+    fn test_stream_round_trip() {
+        let mut compressor = StreamCompressor::new().unwrap();
+        let mut decompressor = StreamDecompressor::new().unwrap();
+        let (mut streamed, mut independent) = (0, 0);
+        for i in 0..3000 {
+            let src = message(i);
+            let prefix = if i % 2 == 0 {
+                SizePrefix::U32LE
+            } else {
+                SizePrefix::None
+            };
+            let compressed = compressor.compress(&src, prefix).unwrap();
+            let size = if i % 2 == 0 {
+                None
+            } else {
+                Some(src.len() as i32)
+            };
+            assert_eq!(
+                decompressor.decompress(&compressed, size, prefix).unwrap(),
+                src
+            );
+            streamed += compressed.len();
+            independent += compress(&src, None, prefix).unwrap().len();
+        }
+        assert!(streamed < independent);
+    }
+
+    #[test]
+    fn test_stream_needs_history() {
+        let mut compressor = StreamCompressor::new().unwrap();
+        compressor.compress(&message(4), SizePrefix::U32LE).unwrap();
+        let compressed = compressor.compress(&message(4), SizePrefix::U32LE).unwrap();
+        assert!(compressed.len() < 20);
+        // Refers to the first message, so it cannot be decompressed on its own
+        assert!(decompress(&compressed, None, SizePrefix::U32LE).is_err());
+
+        let err = compressor
+            .compress_to_buffer(&message(4), SizePrefix::None, &mut [0u8; 20])
+            .unwrap_err();
+        assert_eq!(Error::from(err), Error::DstMaxSizeTooSmall);
+    }
+
+    #[test]
+    fn test_stream_hc() {
+        let mut fast = StreamCompressor::new().unwrap();
+        let mut compressor = StreamCompressorHC::new(9).unwrap();
+        let mut decompressor = StreamDecompressor::new().unwrap();
+        let (mut hc, mut streamed) = (0, 0);
+        for i in 0..1000 {
+            match i {
+                300 => compressor.set_level(12),
+                600 => compressor.favor_decompression_speed(true),
+                900 => compressor.set_level(3),
+                _ => {}
+            }
+            let src = message(i);
+            let compressed = compressor.compress(&src, SizePrefix::Varint).unwrap();
+            let decompressed = decompressor.decompress(&compressed, None, SizePrefix::Varint);
+            assert_eq!(decompressed.unwrap(), src);
+            hc += compressed.len();
+            streamed += fast.compress(&src, SizePrefix::Varint).unwrap().len();
+        }
+        assert!(hc < streamed);
+    }
+}
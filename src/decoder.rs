@@ -1,57 +1,396 @@
+use super::error::Error as LZ4Error;
+use super::frame::{
+    dictionary_id, header_size, FrameCursor, HEADER_PREFIX_SIZE, SKIPPABLE_HEADER_SIZE,
+    SKIPPABLE_MAGIC, SKIPPABLE_MAGIC_MASK,
+};
 use super::liblz4::*;
 use super::size_t;
+use std::cmp;
 use std::io::{Error, ErrorKind, Read, Result};
+use std::mem;
 use std::ptr;
 
 const BUFFER_SIZE: usize = 32 * 1024;
 
+// Minimal LZ4 stream size
+const MIN_FRAME_SIZE: usize = 11;
+
 #[derive(Debug)]
-struct DecoderContext {
-    c: LZ4FDecompressionContext,
+pub(crate) struct DecoderContext {
+    pub(crate) c: LZ4FDecompressionContext,
+}
+
+/// Parameters of a frame, as read from its header.
+#[derive(Clone, Debug)]
+pub struct FrameInfo {
+    pub block_size: BlockSize,
+    pub block_mode: BlockMode,
+    pub content_checksum: ContentChecksum,
+    pub block_checksum: BlockChecksum,
+    /// Uncompressed size of the frame, if the encoder recorded it.
+    pub content_size: Option<u64>,
+    /// Dictionary ID, if the encoder recorded one.
+    pub dict_id: Option<u32>,
+}
+
+/// User data carried by a skippable frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SkippableFrame {
+    /// Low four bits of the magic number (0x184D2A50 to 0x184D2A5F).
+    pub nibble: u8,
+    pub data: Vec<u8>,
+}
+
+/// Totals for the frames read by a `Decoder`.
+#[derive(Clone, Debug)]
+pub struct DecoderStats {
+    /// Compressed bytes consumed, skippable frames included.
+    pub bytes_in: u64,
+    /// Decompressed bytes produced.
+    pub bytes_out: u64,
+    /// Number of frames decoded, not counting skippable frames.
+    pub frames: u64,
+    /// Content checksum of the last frame, if it has one. liblz4 has checked
+    /// it against the decompressed data.
+    pub content_checksum: Option<u32>,
+}
+
+#[derive(Clone, Debug)]
+pub struct DecoderBuilder {
+    multiple_frames: bool,
+    keep_skippable_frames: bool,
+    dictionary: Option<Vec<u8>>,
+    dict_id: Option<u32>,
 }
 
 #[derive(Debug)]
 pub struct Decoder<R> {
     c: DecoderContext,
+    cursor: FrameCursor,
+    info: Option<FrameInfo>,
     r: R,
     buf: Box<[u8]>,
     pos: usize,
     len: usize,
     next: usize,
+    multiple_frames: bool,
+    frames: u64,
+    bytes_in: u64,
+    bytes_out: u64,
+    content_checksum: Option<u32>,
+    // The reader ended before the end of the stream
+    eof: bool,
+    keep_skippable_frames: bool,
+    skippable_frames: Vec<SkippableFrame>,
+    // Skippable frame payload bytes still to be consumed, and the frame being
+    // collected if they are kept.
+    skip: usize,
+    skipped: Option<SkippableFrame>,
+    dictionary: Option<Vec<u8>>,
+    // Expected dictionary ID, if known
+    dict_id: Option<u32>,
 }
 
-impl<R: Read> Decoder<R> {
-    /// Creates a new encoder which will have its output written to the given
-    /// output stream. The output stream can be re-acquired by calling
-    /// `finish()`
-    pub fn new(r: R) -> Result<Decoder<R>> {
+impl Default for DecoderBuilder {
+    fn default() -> Self {
+        DecoderBuilder::new()
+    }
+}
+
+impl DecoderBuilder {
+    pub fn new() -> Self {
+        DecoderBuilder {
+            multiple_frames: false,
+            keep_skippable_frames: false,
+            dictionary: None,
+            dict_id: None,
+        }
+    }
+
+    /// When enabled, the decoder carries on with the next frame once a frame
+    /// ends, so that concatenated frames (e.g. `cat a.lz4 b.lz4`) decode as a
+    /// single stream, as with the reference `lz4` tool. The end of the stream
+    /// is then only detected by reading past the last frame, so the underlying
+    /// reader must not be shared with other data following the frames.
+    pub fn multiple_frames(&mut self, multiple_frames: bool) -> &mut Self {
+        self.multiple_frames = multiple_frames;
+        self
+    }
+
+    /// Skippable frames are always stepped over. When enabled, their contents
+    /// are also collected, to be retrieved with `Decoder::take_skippable_frames()`.
+    pub fn keep_skippable_frames(&mut self, keep_skippable_frames: bool) -> &mut Self {
+        self.keep_skippable_frames = keep_skippable_frames;
+        self
+    }
+
+    /// Decompresses with the dictionary the frames were compressed with. A
+    /// frame header recording an ID other than `dictionary_id(dictionary)`, the
+    /// one written by `EncoderBuilder::dictionary()`, is rejected before any
+    /// data is decoded.
+    pub fn dictionary(&mut self, dictionary: &[u8]) -> &mut Self {
+        self.dictionary_with_id(dictionary, dictionary_id(dictionary))
+    }
+
+    /// Decompresses with the dictionary the frames were compressed with, as
+    /// `dictionary()`, for frames recording `id` as the dictionary ID.
+    pub fn dictionary_with_id(&mut self, dictionary: &[u8], id: u32) -> &mut Self {
+        self.dictionary = Some(dictionary.to_vec());
+        self.dict_id = Some(id);
+        self
+    }
+
+    /// Decompresses with the dictionary the frames were compressed with,
+    /// whatever ID their headers record, for encoders following another
+    /// convention. A wrong dictionary is then only caught by checksums.
+    pub fn dictionary_unchecked(&mut self, dictionary: &[u8]) -> &mut Self {
+        self.dictionary = Some(dictionary.to_vec());
+        self.dict_id = None;
+        self
+    }
+
+    pub fn build<R: Read>(&self, r: R) -> Result<Decoder<R>> {
         Ok(Decoder {
             r,
             c: DecoderContext::new()?,
+            cursor: FrameCursor::new(),
+            info: None,
             buf: vec![0; BUFFER_SIZE].into_boxed_slice(),
             pos: BUFFER_SIZE,
             len: BUFFER_SIZE,
-            // Minimal LZ4 stream size
-            next: 11,
+            next: MIN_FRAME_SIZE,
+            multiple_frames: self.multiple_frames,
+            frames: 0,
+            bytes_in: 0,
+            bytes_out: 0,
+            content_checksum: None,
+            eof: false,
+            keep_skippable_frames: self.keep_skippable_frames,
+            skippable_frames: Vec::new(),
+            skip: 0,
+            skipped: None,
+            dictionary: self.dictionary.clone(),
+            dict_id: self.dict_id,
         })
     }
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new decoder which will read a single frame from the given
+    /// input stream. The input stream can be re-acquired by calling
+    /// `finish()`
+    pub fn new(r: R) -> Result<Decoder<R>> {
+        DecoderBuilder::new().build(r)
+    }
 
     /// Immutable reader reference.
     pub fn reader(&self) -> &R {
         &self.r
     }
 
-    pub fn finish(self) -> (R, Result<()>) {
-        (
-            self.r,
-            match self.next {
-                0 => Ok(()),
-                _ => Err(Error::new(
-                    ErrorKind::Interrupted,
-                    "Finish runned before read end of compressed stream",
-                )),
-            },
-        )
+    #[cfg(any(feature = "tokio", feature = "futures-io"))]
+    pub(crate) fn reader_mut(&mut self) -> &mut R {
+        &mut self.r
+    }
+
+    /// Returns the parameters of the frame being decoded. The header is read
+    /// and parsed on first use if decoding has not started yet.
+    pub fn frame_info(&mut self) -> Result<FrameInfo> {
+        if !self.read_header()? {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "Stream ended before the frame header",
+            ));
+        }
+        Ok(self.info.clone().unwrap())
+    }
+
+    /// Returns the skippable frames collected so far, if the decoder was built
+    /// with `keep_skippable_frames(true)`. Calling `frame_info()` first makes
+    /// sure that those preceding the frame have been read.
+    pub fn take_skippable_frames(&mut self) -> Vec<SkippableFrame> {
+        mem::take(&mut self.skippable_frames)
+    }
+
+    /// Parses the frame header unless that has already been done. Returns
+    /// `false` if the stream ends before the header is complete.
+    fn read_header(&mut self) -> Result<bool> {
+        if self.info.is_some() {
+            return Ok(true);
+        }
+        if !self.skip_frames()? {
+            return Ok(false);
+        }
+        // Leave unknown magic numbers for liblz4 to reject.
+        let size = header_size(&self.buf[self.pos..self.len]).unwrap_or(HEADER_PREFIX_SIZE);
+        if !self.fill(size)? {
+            return Ok(false);
+        }
+        let mut info = LZ4FFrameInfo {
+            block_size_id: BlockSize::Default,
+            block_mode: BlockMode::Linked,
+            content_checksum_flag: ContentChecksum::NoChecksum,
+            frame_type: FrameType::Frame,
+            content_size: 0,
+            dict_id: 0,
+            block_checksum_flag: BlockChecksum::NoBlockChecksum,
+        };
+        let mut src_size = (self.len - self.pos) as size_t;
+        let len = check_error(unsafe {
+            LZ4F_getFrameInfo(
+                self.c.c,
+                &mut info,
+                self.buf[self.pos..].as_ptr(),
+                &mut src_size,
+            )
+        })?;
+        self.cursor
+            .advance(&self.buf[self.pos..self.pos + src_size as usize]);
+        self.pos += src_size as usize;
+        self.bytes_in += src_size as u64;
+        // Exactly the header was buffered, so the hint covers all we need next.
+        self.next = len;
+        self.info = Some(FrameInfo {
+            block_size: info.block_size_id,
+            block_mode: info.block_mode,
+            content_checksum: info.content_checksum_flag,
+            block_checksum: info.block_checksum_flag,
+            content_size: Some(info.content_size).filter(|&size| size != 0),
+            dict_id: Some(info.dict_id).filter(|&id| id != 0),
+        });
+        Ok(true)
+    }
+
+    /// Makes sure the dictionary we were given is the one the current frame
+    /// needs, if its header says so.
+    fn check_dictionary(&self) -> Result<()> {
+        let frame_id = match self.info.as_ref().and_then(|info| info.dict_id) {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+        if self.dictionary.is_none() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Frame requires a dictionary (ID {:#010x})", frame_id),
+            ));
+        }
+        match self.dict_id {
+            Some(id) if id != frame_id => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Wrong dictionary: frame requires ID {:#010x}, given dictionary has ID {:#010x}",
+                    frame_id, id
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Consumes skippable frames until the start of another frame is buffered.
+    /// Returns `false` if the stream ends first.
+    fn skip_frames(&mut self) -> Result<bool> {
+        loop {
+            while self.skip > 0 {
+                if self.pos >= self.len {
+                    let need = cmp::min(self.buf.len(), self.skip);
+                    let len = self.r.read(&mut self.buf[0..need])?;
+                    if len == 0 {
+                        return Ok(false);
+                    }
+                    self.pos = 0;
+                    self.len = len;
+                }
+                let size = cmp::min(self.len - self.pos, self.skip);
+                if let Some(frame) = self.skipped.as_mut() {
+                    frame
+                        .data
+                        .extend_from_slice(&self.buf[self.pos..self.pos + size]);
+                }
+                self.pos += size;
+                self.bytes_in += size as u64;
+                self.skip -= size;
+            }
+            if let Some(frame) = self.skipped.take() {
+                self.skippable_frames.push(frame);
+            }
+
+            if !self.fill(HEADER_PREFIX_SIZE)? {
+                return Ok(false);
+            }
+            let magic = read_u32(&self.buf[self.pos..]);
+            if magic & SKIPPABLE_MAGIC_MASK != SKIPPABLE_MAGIC {
+                return Ok(true);
+            }
+            if !self.fill(SKIPPABLE_HEADER_SIZE)? {
+                return Ok(false);
+            }
+            self.skip = read_u32(&self.buf[self.pos + 4..]) as usize;
+            self.pos += SKIPPABLE_HEADER_SIZE;
+            self.bytes_in += SKIPPABLE_HEADER_SIZE as u64;
+            if self.keep_skippable_frames {
+                self.skipped = Some(SkippableFrame {
+                    nibble: (magic & !SKIPPABLE_MAGIC_MASK) as u8,
+                    data: Vec::new(),
+                });
+            }
+        }
+    }
+
+    /// Whether all input so far has been consumed and it ends on a frame boundary.
+    fn at_frame_boundary(&self) -> bool {
+        self.pos >= self.len && self.skip == 0 && self.skipped.is_none()
+    }
+
+    /// Makes sure at least `size` unconsumed bytes are buffered, without
+    /// reading any further. Returns `false` if the stream ends first.
+    fn fill(&mut self, size: usize) -> Result<bool> {
+        if self.pos >= self.len {
+            self.pos = 0;
+            self.len = 0;
+        } else if self.pos > 0 {
+            self.buf.copy_within(self.pos..self.len, 0);
+            self.len -= self.pos;
+            self.pos = 0;
+        }
+        while self.len < size {
+            let len = self.r.read(&mut self.buf[self.len..size])?;
+            if len == 0 {
+                return Ok(false);
+            }
+            self.len += len;
+        }
+        Ok(true)
+    }
+
+    /// Prepares the context for the frame following the one that just ended.
+    fn reset_frame(&mut self) {
+        unsafe { LZ4F_resetDecompressionContext(self.c.c) };
+        self.cursor = FrameCursor::new();
+        self.info = None;
+        self.next = MIN_FRAME_SIZE;
+    }
+
+    /// Returns the wrapped reader along with totals for the decoded frames.
+    /// Fails with `ErrorKind::UnexpectedEof` if the stream was truncated, as
+    /// reading does, or
+    /// with `ErrorKind::InvalidInput` if it was not read to the end.
+    pub fn finish(self) -> (R, Result<DecoderStats>) {
+        let result = if self.next == 0 {
+            Ok(DecoderStats {
+                bytes_in: self.bytes_in,
+                bytes_out: self.bytes_out,
+                frames: self.frames,
+                content_checksum: self.content_checksum,
+            })
+        } else if self.eof {
+            Err(truncated())
+        } else {
+            Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Finish called before the end of the compressed stream was read",
+            ))
+        };
+        (self.r, result)
     }
 }
 
@@ -62,6 +401,16 @@ impl<R: Read> Read for Decoder<R> {
         }
         let mut dst_offset: usize = 0;
         while dst_offset == 0 {
+            if !self.read_header()? {
+                if self.frames > 0 && self.at_frame_boundary() {
+                    // Clean end of stream between two frames
+                    self.next = 0;
+                    break;
+                }
+                self.eof = true;
+                return Err(truncated());
+            }
+            self.check_dictionary()?;
             if self.pos >= self.len {
                 let need = if self.buf.len() < self.next {
                     self.buf.len()
@@ -70,29 +419,64 @@ impl<R: Read> Read for Decoder<R> {
                 };
                 self.len = self.r.read(&mut self.buf[0..need])?;
                 if self.len == 0 {
-                    break;
+                    self.eof = true;
+                    return Err(truncated());
                 }
                 self.pos = 0;
                 self.next -= self.len;
             }
             while (dst_offset < buf.len()) && (self.pos < self.len) {
-                let mut src_size = (self.len - self.pos) as size_t;
+                // Never hand over more than the current block, so that a checksum
+                // failure can be attributed to it, but always make progress.
+                let remaining = cmp::max(self.cursor.remaining(), 1);
+                let mut src_size = cmp::min(self.len - self.pos, remaining) as size_t;
                 let mut dst_size = (buf.len() - dst_offset) as size_t;
-                let len = check_error(unsafe {
-                    LZ4F_decompress(
-                        self.c.c,
-                        buf[dst_offset..].as_mut_ptr(),
-                        &mut dst_size,
-                        self.buf[self.pos..].as_ptr(),
-                        &mut src_size,
-                        ptr::null(),
-                    )
-                })?;
+                let code = unsafe {
+                    match self.dictionary {
+                        Some(ref dictionary) => LZ4F_decompress_usingDict(
+                            self.c.c,
+                            buf[dst_offset..].as_mut_ptr(),
+                            &mut dst_size,
+                            self.buf[self.pos..].as_ptr(),
+                            &mut src_size,
+                            dictionary.as_ptr(),
+                            dictionary.len() as size_t,
+                            ptr::null(),
+                        ),
+                        None => LZ4F_decompress(
+                            self.c.c,
+                            buf[dst_offset..].as_mut_ptr(),
+                            &mut dst_size,
+                            self.buf[self.pos..].as_ptr(),
+                            &mut src_size,
+                            ptr::null(),
+                        ),
+                    }
+                };
+                if LZ4Error::from_code(unsafe { LZ4F_getErrorCode(code) })
+                    == LZ4Error::BlockChecksumInvalid
+                {
+                    return Err(LZ4Error::BlockChecksumInvalid.with_message(format!(
+                        "Block checksum mismatch in block {}",
+                        self.cursor.block()
+                    )));
+                }
+                let len = check_error(code)?;
+                self.cursor
+                    .advance(&self.buf[self.pos..self.pos + src_size as usize]);
                 self.pos += src_size as usize;
+                self.bytes_in += src_size as u64;
                 dst_offset += dst_size as usize;
+                self.bytes_out += dst_size as u64;
                 if len == 0 {
-                    self.next = 0;
-                    return Ok(dst_offset);
+                    self.frames += 1;
+                    self.content_checksum = self.cursor.content_checksum();
+                    if !self.multiple_frames {
+                        self.next = 0;
+                        return Ok(dst_offset);
+                    }
+                    self.reset_frame();
+                    break;
                 } else if self.next < len {
                     self.next = len;
                 }
@@ -102,8 +486,19 @@ impl<R: Read> Read for Decoder<R> {
     }
 }
 
+fn truncated() -> Error {
+    Error::new(
+        ErrorKind::UnexpectedEof,
+        "Stream ended before the end of the frame",
+    )
+}
+
+fn read_u32(buf: &[u8]) -> u32 {
+    u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]])
+}
+
 impl DecoderContext {
-    fn new() -> Result<DecoderContext> {
+    pub(crate) fn new() -> Result<DecoderContext> {
         let mut context = LZ4FDecompressionContext(ptr::null_mut());
         check_error(unsafe { LZ4F_createDecompressionContext(&mut context, LZ4F_VERSION) })?;
         Ok(DecoderContext { c: context })
@@ -122,9 +517,12 @@ mod test {
 
     use self::rand::rngs::StdRng;
     use self::rand::Rng;
+    use super::super::encoder::write_skippable_frame;
     use super::super::encoder::{Encoder, EncoderBuilder};
-    use super::Decoder;
-    use std::io::{Cursor, Error, ErrorKind, Read, Result, Write};
+    use super::super::frame::dictionary_id;
+    use super::super::liblz4::{BlockChecksum, BlockMode, BlockSize, ContentChecksum};
+    use super::{Decoder, DecoderBuilder, SkippableFrame};
+    use std::io::{self, Cursor, Error, ErrorKind, Read, Result, Write};
 
     const BUFFER_SIZE: usize = 64 * 1024;
     const END_MARK: [u8; 4] = [0x9f, 0x77, 0x22, 0x71];
@@ -240,6 +638,327 @@ mod test {
         finish_decode(decoder);
     }
 
+    #[test]
+    fn test_decoder_content_size() {
+        let expected = b"Some data".to_vec();
+        let mut encoder = EncoderBuilder::new()
+            .content_size(expected.len() as u64)
+            .build(Vec::new())
+            .unwrap();
+        encoder.write_all(&expected).unwrap();
+        let buffer = finish_encode(encoder);
+
+        let mut decoder = Decoder::new(Cursor::new(buffer)).unwrap();
+        let mut actual = Vec::new();
+
+        decoder.read_to_end(&mut actual).unwrap();
+        assert_eq!(expected, actual);
+        finish_decode(decoder);
+    }
+
+    #[test]
+    fn test_decoder_block_checksum() {
+        let mut rnd = random();
+        let expected: Vec<u8> = (0..4 * 64 * 1024).map(|_| rnd.gen_range(0, 4)).collect();
+        let mut encoder = EncoderBuilder::new()
+            .block_size(BlockSize::Max64KB)
+            .block_checksum(BlockChecksum::BlockChecksumEnabled)
+            .build(Vec::new())
+            .unwrap();
+        encoder.write_all(&expected).unwrap();
+        let mut buffer = finish_encode(encoder);
+
+        let mut decoder = Decoder::new(Cursor::new(buffer.clone())).unwrap();
+        let mut actual = Vec::new();
+        decoder.read_to_end(&mut actual).unwrap();
+        assert_eq!(expected, actual);
+
+        // Skip the 7-byte frame header and the first two blocks (size field,
+        // data and checksum each), then damage the third block's data.
+        let mut offset = 7;
+        for _ in 0..2 {
+            let size = u32::from_le_bytes([
+                buffer[offset],
+                buffer[offset + 1],
+                buffer[offset + 2],
+                buffer[offset + 3],
+            ]) & 0x7FFF_FFFF;
+            offset += 4 + size as usize + 4;
+        }
+        buffer[offset + 10] ^= 0xFF;
+
+        let mut decoder = Decoder::new(Cursor::new(buffer)).unwrap();
+        let err = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "Block checksum mismatch in block 2");
+    }
+
+    #[test]
+    fn test_decoder_uncompressed_end_mark() {
+        let mut encoder = EncoderBuilder::new()
+            .checksum(ContentChecksum::ChecksumEnabled)
+            .build(Vec::new())
+            .unwrap();
+        encoder.write_all(b"hello").unwrap();
+        let (mut buffer, result) = encoder.finish();
+        result.unwrap();
+        // An end mark carrying the uncompressed flag still ends the frame.
+        let end = buffer.len() - 8;
+        buffer[end..end + 4].copy_from_slice(&[0x00, 0x00, 0x00, 0x80]);
+
+        let mut decoder = Decoder::new(Cursor::new(buffer)).unwrap();
+        let mut actual = Vec::new();
+        decoder.read_to_end(&mut actual).unwrap();
+        assert_eq!(b"hello".to_vec(), actual);
+        let (_, result) = decoder.finish();
+        result.unwrap();
+    }
+
+    #[test]
+    fn test_decoder_frame_info() {
+        let mut encoder = EncoderBuilder::new()
+            .block_size(BlockSize::Max256KB)
+            .block_mode(BlockMode::Independent)
+            .block_checksum(BlockChecksum::BlockChecksumEnabled)
+            .content_size(9)
+            .build(Vec::new())
+            .unwrap();
+        encoder.write_all(b"Some data").unwrap();
+        let buffer = finish_encode(encoder);
+
+        let mut decoder = Decoder::new(Cursor::new(buffer)).unwrap();
+        let info = decoder.frame_info().unwrap();
+        assert!(matches!(info.block_size, BlockSize::Max256KB));
+        assert!(matches!(info.block_mode, BlockMode::Independent));
+        assert!(matches!(
+            info.content_checksum,
+            ContentChecksum::ChecksumEnabled
+        ));
+        assert!(matches!(
+            info.block_checksum,
+            BlockChecksum::BlockChecksumEnabled
+        ));
+        assert_eq!(info.content_size, Some(9));
+        assert_eq!(info.dict_id, None);
+
+        let mut actual = Vec::new();
+        decoder.read_to_end(&mut actual).unwrap();
+        assert_eq!(b"Some data".to_vec(), actual);
+        finish_decode(decoder);
+    }
+
+    #[test]
+    fn test_decoder_multiple_frames() {
+        let mut buffer = Vec::new();
+        for chunk in &[&b"Some "[..], &b""[..], &b"data"[..]] {
+            let mut encoder = EncoderBuilder::new().build(buffer).unwrap();
+            encoder.write_all(chunk).unwrap();
+            let (w, result) = encoder.finish();
+            result.unwrap();
+            buffer = w;
+        }
+
+        let mut decoder = Decoder::new(Cursor::new(buffer.clone())).unwrap();
+        let mut actual = Vec::new();
+        decoder.read_to_end(&mut actual).unwrap();
+        assert_eq!(b"Some ".to_vec(), actual);
+
+        let mut decoder = DecoderBuilder::new()
+            .multiple_frames(true)
+            .build(Cursor::new(buffer))
+            .unwrap();
+        let mut actual = Vec::new();
+        decoder.read_to_end(&mut actual).unwrap();
+        assert_eq!(b"Some data".to_vec(), actual);
+        let (_, result) = decoder.finish();
+        result.unwrap();
+    }
+
+    #[test]
+    fn test_decoder_multiple_frames_truncated() {
+        let mut encoder = EncoderBuilder::new().build(Vec::new()).unwrap();
+        encoder.write_all(b"Some data").unwrap();
+        let (mut buffer, result) = encoder.finish();
+        result.unwrap();
+        let frame = buffer.clone();
+        buffer.extend_from_slice(&frame[..frame.len() - 3]);
+
+        let mut decoder = DecoderBuilder::new()
+            .multiple_frames(true)
+            .build(Cursor::new(buffer))
+            .unwrap();
+        let mut actual = Vec::new();
+        let err = decoder.read_to_end(&mut actual).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(b"Some dataSome data".to_vec(), actual);
+        let (_, result) = decoder.finish();
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::UnexpectedEof);
+
+        let mut decoder = Decoder::new(Cursor::new(&frame[..frame.len() - 3])).unwrap();
+        let err = io::copy(&mut decoder, &mut io::sink()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_decoder_stats() {
+        let mut encoder = EncoderBuilder::new().build(Vec::new()).unwrap();
+        encoder.write_all(b"Some data").unwrap();
+        let (mut buffer, result) = encoder.finish();
+        let encoded = result.unwrap();
+        let frame = buffer.clone();
+        write_skippable_frame(&mut buffer, 1, b"meta").unwrap();
+        buffer.extend_from_slice(&frame);
+
+        let mut decoder = DecoderBuilder::new()
+            .multiple_frames(true)
+            .build(Cursor::new(&buffer))
+            .unwrap();
+        let mut actual = [0; 4];
+        decoder.read_exact(&mut actual).unwrap();
+        let (_, result) = decoder.finish();
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidInput);
+
+        let mut decoder = DecoderBuilder::new()
+            .multiple_frames(true)
+            .build(Cursor::new(&buffer))
+            .unwrap();
+        decoder.read_to_end(&mut Vec::new()).unwrap();
+        let (_, result) = decoder.finish();
+        let stats = result.unwrap();
+        assert_eq!(stats.bytes_in, buffer.len() as u64);
+        assert_eq!(stats.bytes_out, 18);
+        assert_eq!(stats.frames, 2);
+        assert_eq!(stats.content_checksum, encoded.content_checksum);
+    }
+
+    #[test]
+    fn test_decoder_skippable_frames() {
+        let mut buffer = Vec::new();
+        write_skippable_frame(&mut buffer, 0, b"{\"source\": \"test\"}").unwrap();
+        write_skippable_frame(&mut buffer, 15, b"").unwrap();
+        let mut encoder = EncoderBuilder::new().build(buffer).unwrap();
+        encoder.write_all(b"Some data").unwrap();
+        let (mut buffer, result) = encoder.finish();
+        result.unwrap();
+        write_skippable_frame(&mut buffer, 3, b"trailer").unwrap();
+
+        let mut decoder = Decoder::new(Cursor::new(buffer.clone())).unwrap();
+        let mut actual = Vec::new();
+        decoder.read_to_end(&mut actual).unwrap();
+        assert_eq!(b"Some data".to_vec(), actual);
+        assert!(decoder.take_skippable_frames().is_empty());
+
+        let mut decoder = DecoderBuilder::new()
+            .multiple_frames(true)
+            .keep_skippable_frames(true)
+            .build(Cursor::new(buffer))
+            .unwrap();
+        decoder.frame_info().unwrap();
+        assert_eq!(
+            decoder.take_skippable_frames(),
+            vec![
+                SkippableFrame {
+                    nibble: 0,
+                    data: b"{\"source\": \"test\"}".to_vec(),
+                },
+                SkippableFrame {
+                    nibble: 15,
+                    data: Vec::new(),
+                },
+            ]
+        );
+        let mut actual = Vec::new();
+        decoder.read_to_end(&mut actual).unwrap();
+        assert_eq!(b"Some data".to_vec(), actual);
+        assert_eq!(
+            decoder.take_skippable_frames(),
+            vec![SkippableFrame {
+                nibble: 3,
+                data: b"trailer".to_vec(),
+            }]
+        );
+        let (_, result) = decoder.finish();
+        result.unwrap();
+    }
+
+    #[test]
+    fn test_decoder_dictionary() {
+        let dictionary = b"{\"name\": \"sample\", \"reads\": 0, \"lanes\": [1, 2, 3, 4]}";
+        let expected = b"{\"name\": \"sample\", \"reads\": 7, \"lanes\": [1, 2, 3]}".to_vec();
+
+        let mut encoder = EncoderBuilder::new().build(Vec::new()).unwrap();
+        encoder.write_all(&expected).unwrap();
+        let (plain, result) = encoder.finish();
+        result.unwrap();
+
+        let mut encoder = EncoderBuilder::new()
+            .dictionary(dictionary)
+            .build(Vec::new())
+            .unwrap();
+        encoder.write_all(&expected).unwrap();
+        let (buffer, result) = encoder.finish();
+        result.unwrap();
+        assert!(buffer.len() < plain.len());
+
+        let mut decoder = DecoderBuilder::new()
+            .dictionary(dictionary)
+            .build(Cursor::new(buffer.clone()))
+            .unwrap();
+        assert!(decoder.frame_info().unwrap().dict_id.is_some());
+        let mut actual = Vec::new();
+        decoder.read_to_end(&mut actual).unwrap();
+        assert_eq!(expected, actual);
+
+        let other = b"some other dictionary";
+        let mut decoder = DecoderBuilder::new()
+            .dictionary(other)
+            .build(Cursor::new(buffer.clone()))
+            .unwrap();
+        let err = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert!(err.to_string().starts_with("Wrong dictionary"));
+
+        // Frames from other encoders may record any ID for the dictionary.
+        let mut encoder = EncoderBuilder::new()
+            .dictionary_with_id(dictionary, 7)
+            .build(Vec::new())
+            .unwrap();
+        encoder.write_all(&expected).unwrap();
+        let (foreign, result) = encoder.finish();
+        result.unwrap();
+        assert_eq!(
+            Decoder::new(Cursor::new(foreign.clone()))
+                .unwrap()
+                .frame_info()
+                .unwrap()
+                .dict_id,
+            Some(7)
+        );
+        for id in &[None, Some(7)] {
+            let mut builder = DecoderBuilder::new();
+            match *id {
+                Some(id) => builder.dictionary_with_id(dictionary, id),
+                None => builder.dictionary_unchecked(dictionary),
+            };
+            let mut decoder = builder.build(Cursor::new(foreign.clone())).unwrap();
+            let mut actual = Vec::new();
+            decoder.read_to_end(&mut actual).unwrap();
+            assert_eq!(expected, actual);
+        }
+        for &id in &[dictionary_id(dictionary), 8] {
+            let mut decoder = DecoderBuilder::new()
+                .dictionary_with_id(dictionary, id)
+                .build(Cursor::new(foreign.clone()))
+                .unwrap();
+            let err = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+            assert!(err.to_string().starts_with("Wrong dictionary"));
+        }
+
+        let mut decoder = Decoder::new(Cursor::new(buffer)).unwrap();
+        let err = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
     #[test]
     fn test_decoder_random() {
         let mut rnd = random();
@@ -1,20 +1,39 @@
+use super::error::Error as LZ4Error;
+use super::frame::{dictionary_id, FrameCursor, SKIPPABLE_MAGIC};
 use super::liblz4::*;
 use super::size_t;
 use std::cmp;
+use std::io::Error;
+use std::io::ErrorKind;
 use std::io::Result;
 use std::io::Write;
 use std::ptr;
+use std::sync::Arc;
 
 #[derive(Debug)]
-struct EncoderContext {
-    c: LZ4FCompressionContext,
+pub(crate) struct EncoderContext {
+    pub(crate) c: LZ4FCompressionContext,
 }
 
+#[derive(Debug)]
+struct EncoderDictionary {
+    d: *mut LZ4FCDict,
+    id: u32,
+}
+
+// A digested dictionary is only ever read after creation.
+unsafe impl Send for EncoderDictionary {}
+unsafe impl Sync for EncoderDictionary {}
+
 #[derive(Clone, Debug)]
 pub struct EncoderBuilder {
     block_size: BlockSize,
     block_mode: BlockMode,
     checksum: ContentChecksum,
+    block_checksum: BlockChecksum,
+    // 0 == unknown
+    content_size: u64,
+    dictionary: Option<Arc<EncoderDictionary>>,
     // 0 == default (fast mode); values above 16 count as 16; values below 0 count as 0
     level: u32,
     // 1 == always flush (reduce need for tmp buffer)
@@ -27,6 +46,43 @@ pub struct Encoder<W> {
     w: W,
     limit: usize,
     buffer: Vec<u8>,
+    // Bytes of the buffer already written out; the rest is still pending
+    // after a write error.
+    pos: usize,
+    content_size: u64,
+    checksum: bool,
+    // Follows the output to count blocks
+    cursor: FrameCursor,
+    stats: EncoderStats,
+    finished: bool,
+    // Referenced by the context until the frame is complete
+    _dictionary: Option<Arc<EncoderDictionary>>,
+}
+
+/// Sizes of a frame written by an `Encoder`, so far or in total.
+#[derive(Clone, Debug)]
+pub struct EncoderStats {
+    /// Uncompressed bytes written to the encoder.
+    pub bytes_in: u64,
+    /// Compressed bytes written to the wrapped writer, header and footer included.
+    pub bytes_out: u64,
+    /// Number of complete blocks written.
+    pub blocks: u64,
+    /// Size of the frame header.
+    pub header_size: u64,
+    /// Size of the end mark and content checksum, once the frame is finished.
+    pub footer_size: u64,
+    /// XXH32 checksum of the content as written to the trailer, if the frame
+    /// is finished and has one.
+    pub content_checksum: Option<u32>,
+}
+
+/// Wrapper around an `Encoder` that finishes the frame when dropped, so that
+/// an early return cannot leave the output truncated. Errors raised while
+/// finishing on drop are lost; call `finish()` to handle them.
+#[derive(Debug)]
+pub struct AutoFinishEncoder<W: Write> {
+    encoder: Option<Encoder<W>>,
 }
 
 impl EncoderBuilder {
@@ -35,6 +91,9 @@ impl EncoderBuilder {
             block_size: BlockSize::Default,
             block_mode: BlockMode::Linked,
             checksum: ContentChecksum::ChecksumEnabled,
+            block_checksum: BlockChecksum::NoBlockChecksum,
+            content_size: 0,
+            dictionary: None,
             level: 0,
             auto_flush: false,
         }
@@ -55,6 +114,39 @@ impl EncoderBuilder {
         self
     }
 
+    /// Appends an XXH32 checksum of the compressed data to every block, so that
+    /// corruption is detected as soon as the damaged block is read.
+    pub fn block_checksum(&mut self, block_checksum: BlockChecksum) -> &mut Self {
+        self.block_checksum = block_checksum;
+        self
+    }
+
+    /// Declares the total uncompressed size of the stream, which is stored in
+    /// the frame header. `0` means unknown and omits the field. `finish()`
+    /// fails if the number of bytes written differs from the declared size.
+    pub fn content_size(&mut self, content_size: u64) -> &mut Self {
+        self.content_size = content_size;
+        self
+    }
+
+    /// Compresses with a predefined dictionary, which greatly improves the ratio
+    /// for small inputs resembling it. The dictionary is digested once and
+    /// shared by every encoder built from this builder. Its ID (an XXH32 hash of
+    /// its contents, a convention of this crate) is written to the frame header,
+    /// and the same dictionary must be given to `DecoderBuilder::dictionary()`
+    /// to decode the frame.
+    pub fn dictionary(&mut self, dictionary: &[u8]) -> &mut Self {
+        self.dictionary_with_id(dictionary, dictionary_id(dictionary))
+    }
+
+    /// Compresses with a predefined dictionary, as `dictionary()`, recording
+    /// the given ID in the frame header instead, e.g. one agreed upon with
+    /// other LZ4 implementations. `0` omits the field.
+    pub fn dictionary_with_id(&mut self, dictionary: &[u8], id: u32) -> &mut Self {
+        self.dictionary = Some(Arc::new(EncoderDictionary::new(dictionary, id)));
+        self
+    }
+
     pub fn level(&mut self, level: u32) -> &mut Self {
         self.level = level;
         self
@@ -65,19 +157,33 @@ impl EncoderBuilder {
         self
     }
 
-    pub fn build<W: Write>(&self, w: W) -> Result<Encoder<W>> {
-        let block_size = self.block_size.get_size();
-        let preferences = LZ4FPreferences {
+    pub(crate) fn preferences(&self) -> LZ4FPreferences {
+        LZ4FPreferences {
             frame_info: LZ4FFrameInfo {
                 block_size_id: self.block_size.clone(),
                 block_mode: self.block_mode.clone(),
                 content_checksum_flag: self.checksum.clone(),
-                reserved: [0; 5],
+                frame_type: FrameType::Frame,
+                content_size: self.content_size,
+                dict_id: self.dictionary.as_ref().map_or(0, |d| d.id),
+                block_checksum_flag: self.block_checksum.clone(),
             },
             compression_level: self.level,
             auto_flush: if self.auto_flush { 1 } else { 0 },
             reserved: [0; 4],
-        };
+        }
+    }
+
+    pub fn build<W: Write>(&self, w: W) -> Result<Encoder<W>> {
+        let mut encoder = self.build_pending(w)?;
+        encoder.write_buffer()?;
+        Ok(encoder)
+    }
+
+    /// Creates an encoder whose frame header is produced but not yet written.
+    pub(crate) fn build_pending<W>(&self, w: W) -> Result<Encoder<W>> {
+        let block_size = self.block_size.get_size();
+        let preferences = self.preferences();
         let mut encoder = Encoder {
             w,
             c: EncoderContext::new()?,
@@ -85,37 +191,52 @@ impl EncoderBuilder {
             buffer: Vec::with_capacity(check_error(unsafe {
                 LZ4F_compressBound(block_size as size_t, &preferences)
             })?),
+            pos: 0,
+            content_size: self.content_size,
+            checksum: matches!(self.checksum, ContentChecksum::ChecksumEnabled),
+            cursor: FrameCursor::new(),
+            stats: EncoderStats {
+                bytes_in: 0,
+                bytes_out: 0,
+                blocks: 0,
+                header_size: 0,
+                footer_size: 0,
+                content_checksum: None,
+            },
+            finished: false,
+            _dictionary: self.dictionary.clone(),
+        };
+        let cdict = match self.dictionary {
+            Some(ref dictionary) if dictionary.d.is_null() => {
+                return Err(LZ4Error::AllocationFailed
+                    .with_message("Failed to create compression dictionary"));
+            }
+            Some(ref dictionary) => dictionary.d,
+            None => ptr::null_mut(),
         };
-        encoder.write_header(&preferences)?;
+        encoder.write_header(&preferences, cdict)?;
         Ok(encoder)
     }
 }
 
-impl<W: Write> Encoder<W> {
-    fn write_header(&mut self, preferences: &LZ4FPreferences) -> Result<()> {
+impl<W> Encoder<W> {
+    fn write_header(
+        &mut self,
+        preferences: &LZ4FPreferences,
+        cdict: *const LZ4FCDict,
+    ) -> Result<()> {
         unsafe {
-            let len = check_error(LZ4F_compressBegin(
+            let len = check_error(LZ4F_compressBegin_usingCDict(
                 self.c.c,
                 self.buffer.as_mut_ptr(),
                 self.buffer.capacity() as size_t,
+                cdict,
                 preferences,
             ))?;
             self.buffer.set_len(len);
         }
-        self.w.write_all(&self.buffer)
-    }
-
-    fn write_end(&mut self) -> Result<()> {
-        unsafe {
-            let len = check_error(LZ4F_compressEnd(
-                self.c.c,
-                self.buffer.as_mut_ptr(),
-                self.buffer.capacity() as size_t,
-                ptr::null(),
-            ))?;
-            self.buffer.set_len(len);
-        };
-        self.w.write_all(&self.buffer)
+        self.stats.header_size = self.buffer.len() as u64;
+        Ok(())
     }
 
     /// Immutable writer reference.
@@ -123,59 +244,231 @@ impl<W: Write> Encoder<W> {
         &self.w
     }
 
+    /// Mutable writer reference. Writing to it directly corrupts the frame
+    /// unless done after the frame is finished.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.w
+    }
+
+    /// Sizes of the frame written so far.
+    pub fn stats(&self) -> EncoderStats {
+        self.stats.clone()
+    }
+
+    #[cfg(any(feature = "tokio", feature = "futures-io"))]
+    pub(crate) fn into_writer(self) -> W {
+        self.w
+    }
+}
+
+impl<W: Write> Encoder<W> {
+    /// Writes out what the context produced and accounts for it. What could
+    /// not be written because of an error is kept for the next call.
+    fn write_buffer(&mut self) -> Result<()> {
+        while self.pos < self.buffer.len() {
+            let len = match self.w.write(&self.buffer[self.pos..]) {
+                Ok(0) => {
+                    return Err(Error::new(
+                        ErrorKind::WriteZero,
+                        "failed to write whole buffer",
+                    ))
+                }
+                Ok(len) => len,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            self.cursor.advance(&self.buffer[self.pos..self.pos + len]);
+            self.stats.bytes_out += len as u64;
+            self.stats.blocks = self.cursor.block();
+            self.pos += len;
+        }
+        Ok(())
+    }
+
+    /// Lets the context produce into the buffer, once its previous contents
+    /// are written out.
+    fn produce<F: FnOnce(&mut Vec<u8>) -> Result<usize>>(&mut self, f: F) -> Result<()> {
+        self.write_buffer()?;
+        let len = f(&mut self.buffer)?;
+        unsafe { self.buffer.set_len(len) };
+        self.pos = 0;
+        Ok(())
+    }
+
+    fn write_end(&mut self) -> Result<()> {
+        if self.content_size != 0 && self.content_size != self.stats.bytes_in {
+            return Err(LZ4Error::FrameSizeWrong.with_message(format!(
+                "Declared content size is {} bytes, but {} bytes were written",
+                self.content_size, self.stats.bytes_in
+            )));
+        }
+        let c = self.c.c;
+        self.produce(|buffer| {
+            check_error(unsafe {
+                LZ4F_compressEnd(
+                    c,
+                    buffer.as_mut_ptr(),
+                    buffer.capacity() as size_t,
+                    ptr::null(),
+                )
+            })
+        })?;
+        self.finished = true;
+        // The output ends with the end mark, then the optional content checksum.
+        let footer = &self.buffer[self.buffer.len() - if self.checksum { 8 } else { 4 }..];
+        self.stats.footer_size = footer.len() as u64;
+        if self.checksum {
+            self.stats.content_checksum = Some(u32::from_le_bytes([
+                footer[4], footer[5], footer[6], footer[7],
+            ]));
+        }
+        Ok(())
+    }
+
+    /// Writes the end of the frame, keeping the wrapped writer in place, and
+    /// returns the final statistics. Calling it again does nothing, and
+    /// writing more data is an error.
+    pub fn try_finish(&mut self) -> Result<EncoderStats> {
+        if !self.finished {
+            self.write_end()?;
+        }
+        self.write_buffer()?;
+        Ok(self.stats())
+    }
+
     /// This function is used to flag that this session of compression is done
     /// with. The stream is finished up (final bytes are written), and then the
-    /// wrapped writer is returned.
-    pub fn finish(mut self) -> (W, Result<()>) {
-        let result = self.write_end();
+    /// wrapped writer is returned along with the final statistics.
+    pub fn finish(mut self) -> (W, Result<EncoderStats>) {
+        let result = self.try_finish();
         (self.w, result)
     }
+
+    /// Wraps the encoder so that the frame is finished when it goes out of scope.
+    pub fn auto_finish(self) -> AutoFinishEncoder<W> {
+        AutoFinishEncoder {
+            encoder: Some(self),
+        }
+    }
 }
 
 impl<W: Write> Write for Encoder<W> {
     fn write(&mut self, buffer: &[u8]) -> Result<usize> {
+        if self.finished {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Cannot write to an encoder whose frame is finished",
+            ));
+        }
         let mut offset = 0;
         while offset < buffer.len() {
             let size = cmp::min(buffer.len() - offset, self.limit);
-            unsafe {
-                let len = check_error(LZ4F_compressUpdate(
-                    self.c.c,
-                    self.buffer.as_mut_ptr(),
-                    self.buffer.capacity() as size_t,
-                    buffer[offset..].as_ptr(),
-                    size as size_t,
-                    ptr::null(),
-                ))?;
-                self.buffer.set_len(len);
-                self.w.write_all(&self.buffer)?;
+            let (c, src) = (self.c.c, &buffer[offset..offset + size]);
+            let result = self.produce(|out| {
+                check_error(unsafe {
+                    LZ4F_compressUpdate(
+                        c,
+                        out.as_mut_ptr(),
+                        out.capacity() as size_t,
+                        src.as_ptr(),
+                        size as size_t,
+                        ptr::null(),
+                    )
+                })
+            });
+            if let Err(e) = result {
+                // Input already taken must be reported; the error comes back
+                // on the next call.
+                return if offset > 0 { Ok(offset) } else { Err(e) };
             }
             offset += size;
+            self.stats.bytes_in += size as u64;
         }
-        Ok(buffer.len())
+        // Output that cannot be written now is kept for the next call.
+        let _ = self.write_buffer();
+        Ok(offset)
     }
 
     fn flush(&mut self) -> Result<()> {
-        loop {
-            unsafe {
-                let len = check_error(LZ4F_flush(
-                    self.c.c,
-                    self.buffer.as_mut_ptr(),
-                    self.buffer.capacity() as size_t,
-                    ptr::null(),
-                ))?;
-                if len == 0 {
-                    break;
-                }
-                self.buffer.set_len(len);
-            };
-            self.w.write_all(&self.buffer)?;
+        while !self.finished {
+            let c = self.c.c;
+            self.produce(|out| {
+                check_error(unsafe {
+                    LZ4F_flush(c, out.as_mut_ptr(), out.capacity() as size_t, ptr::null())
+                })
+            })?;
+            if self.buffer.is_empty() {
+                break;
+            }
         }
+        self.write_buffer()?;
         self.w.flush()
     }
 }
 
+impl<W: Write> AutoFinishEncoder<W> {
+    /// Immutable writer reference.
+    pub fn writer(&self) -> &W {
+        self.encoder.as_ref().unwrap().writer()
+    }
+
+    /// Mutable writer reference, see `Encoder::get_mut()`.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.encoder.as_mut().unwrap().get_mut()
+    }
+
+    /// Sizes of the frame written so far.
+    pub fn stats(&self) -> EncoderStats {
+        self.encoder.as_ref().unwrap().stats()
+    }
+
+    /// Finishes the frame and returns the wrapped writer, reporting any error.
+    pub fn finish(mut self) -> (W, Result<EncoderStats>) {
+        self.encoder.take().unwrap().finish()
+    }
+}
+
+impl<W: Write> Write for AutoFinishEncoder<W> {
+    fn write(&mut self, buffer: &[u8]) -> Result<usize> {
+        self.encoder.as_mut().unwrap().write(buffer)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.encoder.as_mut().unwrap().flush()
+    }
+}
+
+impl<W: Write> Drop for AutoFinishEncoder<W> {
+    fn drop(&mut self) {
+        if let Some(mut encoder) = self.encoder.take() {
+            let _ = encoder.try_finish();
+        }
+    }
+}
+
+/// Writes a skippable frame carrying `data`, which LZ4 decoders step over.
+/// `nibble` (0 to 15) selects the magic number, from 0x184D2A50 to 0x184D2A5F,
+/// and can be used to tell different kinds of user data apart.
+pub fn write_skippable_frame<W: Write>(mut w: W, nibble: u8, data: &[u8]) -> Result<()> {
+    if nibble > 0x0F {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Skippable frame nibble must be between 0 and 15",
+        ));
+    }
+    if data.len() > u32::MAX as usize {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Skippable frame data too long",
+        ));
+    }
+    w.write_all(&(SKIPPABLE_MAGIC | nibble as u32).to_le_bytes())?;
+    w.write_all(&(data.len() as u32).to_le_bytes())?;
+    w.write_all(data)
+}
+
 impl EncoderContext {
-    fn new() -> Result<EncoderContext> {
+    pub(crate) fn new() -> Result<EncoderContext> {
         let mut context = LZ4FCompressionContext(ptr::null_mut());
         check_error(unsafe { LZ4F_createCompressionContext(&mut context, LZ4F_VERSION) })?;
         Ok(EncoderContext { c: context })
@@ -188,9 +481,26 @@ impl Drop for EncoderContext {
     }
 }
 
+impl EncoderDictionary {
+    fn new(dictionary: &[u8], id: u32) -> EncoderDictionary {
+        EncoderDictionary {
+            d: unsafe { LZ4F_createCDict(dictionary.as_ptr(), dictionary.len() as size_t) },
+            id,
+        }
+    }
+}
+
+impl Drop for EncoderDictionary {
+    fn drop(&mut self) {
+        unsafe { LZ4F_freeCDict(self.d) };
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::EncoderBuilder;
+    use crate::liblz4::{BlockChecksum, XXH32};
+    use crate::size_t;
     use std::io::Write;
 
     #[test]
@@ -216,6 +526,86 @@ mod test {
         result.unwrap();
     }
 
+    #[test]
+    fn test_encoder_content_size() {
+        let mut encoder = EncoderBuilder::new()
+            .content_size(9)
+            .build(Vec::new())
+            .unwrap();
+        encoder.write_all(b"Some data").unwrap();
+        let (buffer, result) = encoder.finish();
+        result.unwrap();
+        // FLG byte follows the 4-byte magic number; bit 3 is the content size flag
+        assert_ne!(buffer[4] & 0x08, 0);
+        assert_eq!(&buffer[6..14], &9u64.to_le_bytes());
+
+        let mut encoder = EncoderBuilder::new()
+            .content_size(10)
+            .build(Vec::new())
+            .unwrap();
+        encoder.write_all(b"Some data").unwrap();
+        let (_, result) = encoder.finish();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_encoder_try_finish() {
+        let mut encoder = EncoderBuilder::new().build(Vec::new()).unwrap();
+        encoder.write_all(b"Some data").unwrap();
+        encoder.try_finish().unwrap();
+        let len = encoder.writer().len();
+        encoder.try_finish().unwrap();
+        assert_eq!(encoder.writer().len(), len);
+        assert!(encoder.write_all(b"More data").is_err());
+        encoder.get_mut().extend_from_slice(b"trailer");
+        let (buffer, result) = encoder.finish();
+        result.unwrap();
+        assert_eq!(&buffer[len..], b"trailer");
+    }
+
+    #[test]
+    fn test_encoder_stats() {
+        let data = vec![7u8; 200 * 1024];
+        let mut encoder = EncoderBuilder::new()
+            .block_checksum(BlockChecksum::BlockChecksumEnabled)
+            .build(Vec::new())
+            .unwrap();
+        encoder.write_all(&data).unwrap();
+        let stats = encoder.stats();
+        assert_eq!(stats.bytes_in, data.len() as u64);
+        assert_eq!(stats.blocks, 3);
+        assert_eq!(stats.header_size, 7);
+        assert_eq!(stats.footer_size, 0);
+        assert!(stats.content_checksum.is_none());
+
+        let (buffer, result) = encoder.finish();
+        let stats = result.unwrap();
+        assert_eq!(stats.bytes_out, buffer.len() as u64);
+        assert_eq!(stats.blocks, 4);
+        assert_eq!(stats.footer_size, 8);
+        let expected = unsafe { XXH32(data.as_ptr(), data.len() as size_t, 0) };
+        assert_eq!(stats.content_checksum, Some(expected));
+        assert_eq!(&buffer[buffer.len() - 4..], &expected.to_le_bytes());
+    }
+
+    #[test]
+    fn test_auto_finish_encoder() {
+        let mut expected = EncoderBuilder::new().build(Vec::new()).unwrap();
+        expected.write_all(b"Some data").unwrap();
+        let (expected, result) = expected.finish();
+        result.unwrap();
+
+        let mut buffer = Vec::new();
+        {
+            let mut encoder = EncoderBuilder::new()
+                .build(&mut buffer)
+                .unwrap()
+                .auto_finish();
+            encoder.write_all(b"Some data").unwrap();
+        }
+        assert_eq!(buffer, expected);
+    }
+
     #[test]
     fn test_encoder_send() {
         fn check_send<S: Send>(_: &S) {}
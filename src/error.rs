@@ -0,0 +1,240 @@
+use std::error;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+
+/// Failures reported by liblz4, one variant per `LZ4F_errorCodes` value, plus
+/// those of the block functions. Every function of this crate returns
+/// `io::Error`; converting it back with `Error::from` recovers the variant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    Generic,
+    MaxBlockSizeInvalid,
+    BlockModeInvalid,
+    ContentChecksumFlagInvalid,
+    CompressionLevelInvalid,
+    HeaderVersionWrong,
+    BlockChecksumInvalid,
+    ReservedFlagSet,
+    AllocationFailed,
+    SrcSizeTooLarge,
+    DstMaxSizeTooSmall,
+    FrameHeaderIncomplete,
+    /// The input does not start with a known magic number.
+    FrameTypeUnknown,
+    FrameSizeWrong,
+    SrcPtrWrong,
+    DecompressionFailed,
+    HeaderChecksumInvalid,
+    ContentChecksumInvalid,
+    FrameDecodingAlreadyStarted,
+    /// Block compression failed inside liblz4, which gives no reason.
+    CompressionFailed,
+    /// A size-prefixed block is shorter than its prefix.
+    SizePrefixMissing,
+    /// The uncompressed size given or read from a prefix is missing, negative or too large.
+    UncompressedSizeInvalid,
+    /// A block decompresses to more than the size limit given.
+    SizeLimitExceeded,
+    /// An I/O error that did not come from liblz4, such as one raised by the
+    /// wrapped reader or writer. Only its kind is kept.
+    Io(io::ErrorKind),
+}
+
+/// Error message reported by liblz4, as returned before `Error` existed.
+#[deprecated(note = "errors now convert to `lz4::Error`, which tells them apart")]
+#[derive(Debug)]
+pub struct LZ4Error(String);
+
+#[allow(deprecated)]
+impl Display for LZ4Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "LZ4 error: {}", &self.0)
+    }
+}
+
+#[allow(deprecated)]
+impl error::Error for LZ4Error {}
+
+/// An `Error` whose message adds details, such as which block failed.
+#[derive(Debug)]
+struct Detailed {
+    error: Error,
+    message: String,
+}
+
+impl Error {
+    /// Maps the value returned by `LZ4F_getErrorCode`.
+    pub(crate) fn from_code(code: u32) -> Error {
+        match code {
+            2 => Error::MaxBlockSizeInvalid,
+            3 => Error::BlockModeInvalid,
+            4 => Error::ContentChecksumFlagInvalid,
+            5 => Error::CompressionLevelInvalid,
+            6 => Error::HeaderVersionWrong,
+            7 => Error::BlockChecksumInvalid,
+            8 => Error::ReservedFlagSet,
+            9 => Error::AllocationFailed,
+            10 => Error::SrcSizeTooLarge,
+            11 => Error::DstMaxSizeTooSmall,
+            12 => Error::FrameHeaderIncomplete,
+            13 => Error::FrameTypeUnknown,
+            14 => Error::FrameSizeWrong,
+            15 => Error::SrcPtrWrong,
+            16 => Error::DecompressionFailed,
+            17 => Error::HeaderChecksumInvalid,
+            18 => Error::ContentChecksumInvalid,
+            19 => Error::FrameDecodingAlreadyStarted,
+            _ => Error::Generic,
+        }
+    }
+
+    /// The `io::ErrorKind` used when converting to `io::Error`.
+    pub fn kind(&self) -> io::ErrorKind {
+        match *self {
+            Error::MaxBlockSizeInvalid
+            | Error::BlockModeInvalid
+            | Error::ContentChecksumFlagInvalid
+            | Error::CompressionLevelInvalid
+            | Error::SrcSizeTooLarge
+            | Error::DstMaxSizeTooSmall
+            | Error::SrcPtrWrong
+            | Error::FrameDecodingAlreadyStarted
+            | Error::SizePrefixMissing
+            | Error::UncompressedSizeInvalid => io::ErrorKind::InvalidInput,
+            Error::HeaderVersionWrong
+            | Error::BlockChecksumInvalid
+            | Error::ReservedFlagSet
+            | Error::FrameHeaderIncomplete
+            | Error::FrameTypeUnknown
+            | Error::FrameSizeWrong
+            | Error::DecompressionFailed
+            | Error::HeaderChecksumInvalid
+            | Error::ContentChecksumInvalid
+            | Error::SizeLimitExceeded => io::ErrorKind::InvalidData,
+            Error::Generic | Error::AllocationFailed | Error::CompressionFailed => {
+                io::ErrorKind::Other
+            }
+            Error::Io(kind) => kind,
+        }
+    }
+
+    /// Converts to an `io::Error` displaying `message` instead of the usual
+    /// description.
+    pub(crate) fn with_message<M: Into<String>>(self, message: M) -> io::Error {
+        io::Error::new(
+            self.kind(),
+            Detailed {
+                error: self,
+                message: message.into(),
+            },
+        )
+    }
+
+    fn description(&self) -> &'static str {
+        match *self {
+            Error::Generic => "Unspecified LZ4 error",
+            Error::MaxBlockSizeInvalid => "Invalid maximum block size",
+            Error::BlockModeInvalid => "Invalid block mode",
+            Error::ContentChecksumFlagInvalid => "Invalid content checksum flag",
+            Error::CompressionLevelInvalid => "Invalid compression level",
+            Error::HeaderVersionWrong => "Unsupported frame version",
+            Error::BlockChecksumInvalid => "Block checksum mismatch",
+            Error::ReservedFlagSet => "Reserved frame header flag is set",
+            Error::AllocationFailed => "Allocation failed",
+            Error::SrcSizeTooLarge => "Input too large",
+            Error::DstMaxSizeTooSmall => "Destination buffer too small",
+            Error::FrameHeaderIncomplete => "Frame header incomplete",
+            Error::FrameTypeUnknown => "Unknown frame type (wrong magic number)",
+            Error::FrameSizeWrong => "Frame size does not match the declared content size",
+            Error::SrcPtrWrong => "Input pointer changed while decoding",
+            Error::DecompressionFailed => "Decompression failed, input is corrupted",
+            Error::HeaderChecksumInvalid => "Frame header checksum mismatch",
+            Error::ContentChecksumInvalid => "Content checksum mismatch",
+            Error::FrameDecodingAlreadyStarted => "Frame decoding already started",
+            Error::CompressionFailed => "Compression failed",
+            Error::SizePrefixMissing => "Source buffer must at least contain size prefix",
+            Error::UncompressedSizeInvalid => "Uncompressed size is missing, negative or too large",
+            Error::SizeLimitExceeded => "Decompressed size exceeds the limit",
+            Error::Io(_) => "I/O error",
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(kind) => write!(f, "I/O error: {:?}", kind),
+            _ => f.write_str(self.description()),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl Display for Detailed {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl error::Error for Detailed {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(error: Error) -> io::Error {
+        match error {
+            Error::Io(kind) => io::Error::from(kind),
+            _ => io::Error::new(error.kind(), error),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        if let Some(inner) = error.get_ref() {
+            if let Some(error) = inner.downcast_ref::<Error>() {
+                return *error;
+            }
+            if let Some(detailed) = inner.downcast_ref::<Detailed>() {
+                return detailed.error;
+            }
+        }
+        Error::Io(error.kind())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Error;
+    use crate::Decoder;
+    use std::io::{self, Read};
+
+    #[test]
+    fn test_error_io_round_trip() {
+        let error = io::Error::from(Error::ContentChecksumInvalid);
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(Error::from(error), Error::ContentChecksumInvalid);
+
+        let error = Error::BlockChecksumInvalid.with_message("Block checksum mismatch in block 3");
+        assert_eq!(error.to_string(), "Block checksum mismatch in block 3");
+        assert_eq!(Error::from(error), Error::BlockChecksumInvalid);
+
+        let error = io::Error::new(io::ErrorKind::BrokenPipe, "closed");
+        assert_eq!(Error::from(error), Error::Io(io::ErrorKind::BrokenPipe));
+    }
+
+    #[test]
+    fn test_error_liblz4_name() {
+        let mut decoder = Decoder::new(&b"not an lz4 frame"[..]).unwrap();
+        let error = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Unknown frame type (wrong magic number) (ERROR_frameType_unknown)"
+        );
+        assert_eq!(Error::from(error), Error::FrameTypeUnknown);
+    }
+}
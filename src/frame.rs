@@ -0,0 +1,241 @@
+//! Follows the layout of an LZ4 frame while its bytes are being fed to the
+//! decompression context, so that the decoder knows which block it is in.
+//! No decoding or validation happens here; that is left to liblz4.
+
+use super::liblz4::*;
+use super::size_t;
+use crate::Error;
+use std::cmp;
+use std::io::Result;
+
+/// Magic number at the start of every LZ4 frame.
+pub const MAGIC: u32 = 0x184D_2204;
+
+/// Magic numbers 0x184D2A50 to 0x184D2A5F start a skippable frame.
+pub const SKIPPABLE_MAGIC: u32 = 0x184D_2A50;
+pub const SKIPPABLE_MAGIC_MASK: u32 = 0xFFFF_FFF0;
+
+/// Size of a skippable frame header: magic number and little-endian payload size.
+pub const SKIPPABLE_HEADER_SIZE: usize = 8;
+
+/// Number of leading header bytes needed by `header_size`.
+pub const HEADER_PREFIX_SIZE: usize = 5;
+
+/// Size of the frame header without the optional content size and dictionary ID.
+const MIN_HEADER_SIZE: usize = 7;
+
+const FLG_BLOCK_CHECKSUM: u8 = 0x10;
+const FLG_CONTENT_SIZE: u8 = 0x08;
+const FLG_CONTENT_CHECKSUM: u8 = 0x04;
+const FLG_DICT_ID: u8 = 0x01;
+
+/// High bit of the block size field marks a block stored uncompressed.
+pub const BLOCK_UNCOMPRESSED: u32 = 0x8000_0000;
+
+/// Returns the full size of a frame header from its first `HEADER_PREFIX_SIZE`
+/// bytes (magic number and FLG byte), or `None` if they do not start an LZ4 frame.
+pub fn header_size(prefix: &[u8]) -> Option<usize> {
+    let magic = u32::from_le_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]);
+    if magic != MAGIC {
+        return None;
+    }
+    let flg = prefix[4];
+    let mut size = MIN_HEADER_SIZE;
+    if flg & FLG_CONTENT_SIZE != 0 {
+        size += 8;
+    }
+    if flg & FLG_DICT_ID != 0 {
+        size += 4;
+    }
+    Some(size)
+}
+
+/// Dictionary ID recorded by `EncoderBuilder::dictionary()` in the header of
+/// frames compressed with `dictionary`: its XXH32 hash, avoiding 0 which
+/// stands for "no dictionary".
+pub fn dictionary_id(dictionary: &[u8]) -> u32 {
+    let hash = unsafe { XXH32(dictionary.as_ptr(), dictionary.len() as size_t, 0) };
+    cmp::max(hash, 1)
+}
+
+/// Streaming XXH32 with seed 0, as used for content checksums.
+#[derive(Debug)]
+pub struct ContentHasher {
+    s: *mut XXH32State,
+}
+
+// The state is plain memory owned by this handle.
+unsafe impl Send for ContentHasher {}
+
+impl ContentHasher {
+    pub fn new() -> Result<ContentHasher> {
+        let s = unsafe { XXH32_createState() };
+        if s.is_null() {
+            return Err(Error::AllocationFailed.into());
+        }
+        unsafe { XXH32_reset(s, 0) };
+        Ok(ContentHasher { s })
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        unsafe { XXH32_update(self.s, data.as_ptr(), data.len() as size_t) };
+    }
+
+    pub fn digest(&self) -> u32 {
+        unsafe { XXH32_digest(self.s) }
+    }
+}
+
+impl Drop for ContentHasher {
+    fn drop(&mut self) {
+        unsafe { XXH32_freeState(self.s) };
+    }
+}
+
+#[derive(Debug)]
+enum Stage {
+    Header { pos: usize, len: usize },
+    BlockSize { pos: usize },
+    Block { remaining: usize },
+    ContentChecksum { remaining: usize },
+    // Not something we know how to follow; liblz4 will report the error.
+    Unknown,
+    End,
+}
+
+#[derive(Debug)]
+pub struct FrameCursor {
+    stage: Stage,
+    // Magic number and FLG byte of the header, the current block size field or
+    // the content checksum.
+    buf: [u8; HEADER_PREFIX_SIZE],
+    block_checksum: bool,
+    content_checksum: bool,
+    block: u64,
+}
+
+impl FrameCursor {
+    pub fn new() -> Self {
+        FrameCursor {
+            stage: Stage::Header {
+                pos: 0,
+                len: MIN_HEADER_SIZE,
+            },
+            buf: [0; HEADER_PREFIX_SIZE],
+            block_checksum: false,
+            content_checksum: false,
+            block: 0,
+        }
+    }
+
+    /// Zero-based index of the block currently being consumed.
+    pub fn block(&self) -> u64 {
+        self.block
+    }
+
+    /// Number of bytes left in the current part of the frame (header, block size
+    /// field, block or trailer). Feeding no more than this to the decompression
+    /// context guarantees that a call never spans two blocks.
+    pub fn remaining(&self) -> usize {
+        match self.stage {
+            Stage::Header { pos, len } => len - pos,
+            Stage::BlockSize { pos } => 4 - pos,
+            Stage::Block { remaining } | Stage::ContentChecksum { remaining } => remaining,
+            Stage::Unknown | Stage::End => usize::MAX,
+        }
+    }
+
+    /// Content checksum read from the trailer, once the frame has ended.
+    pub fn content_checksum(&self) -> Option<u32> {
+        match self.stage {
+            Stage::End if self.content_checksum => Some(u32::from_le_bytes([
+                self.buf[0],
+                self.buf[1],
+                self.buf[2],
+                self.buf[3],
+            ])),
+            _ => None,
+        }
+    }
+
+    /// Moves the cursor past `data`, which must be the bytes that the
+    /// decompression context has just consumed.
+    pub fn advance(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let take = self.remaining().min(data.len());
+            let (chunk, rest) = data.split_at(take);
+            data = rest;
+            self.stage = match self.stage {
+                Stage::Header { pos, len } => self.advance_header(chunk, pos, len),
+                Stage::BlockSize { pos } => {
+                    self.buf[pos..pos + take].copy_from_slice(chunk);
+                    if pos + take < 4 {
+                        Stage::BlockSize { pos: pos + take }
+                    } else {
+                        self.block_stage()
+                    }
+                }
+                Stage::Block { remaining } if remaining == take => {
+                    self.block += 1;
+                    Stage::BlockSize { pos: 0 }
+                }
+                Stage::Block { remaining } => Stage::Block {
+                    remaining: remaining - take,
+                },
+                Stage::ContentChecksum { remaining } => {
+                    let pos = 4 - remaining;
+                    self.buf[pos..pos + take].copy_from_slice(chunk);
+                    if remaining == take {
+                        Stage::End
+                    } else {
+                        Stage::ContentChecksum {
+                            remaining: remaining - take,
+                        }
+                    }
+                }
+                Stage::Unknown => Stage::Unknown,
+                Stage::End => Stage::End,
+            };
+        }
+    }
+
+    fn advance_header(&mut self, chunk: &[u8], pos: usize, mut len: usize) -> Stage {
+        let end = pos + chunk.len();
+        if pos < HEADER_PREFIX_SIZE {
+            let n = HEADER_PREFIX_SIZE.min(end) - pos;
+            self.buf[pos..pos + n].copy_from_slice(&chunk[..n]);
+        }
+        if pos < HEADER_PREFIX_SIZE && end >= HEADER_PREFIX_SIZE {
+            len = match header_size(&self.buf) {
+                Some(size) => size,
+                None => return Stage::Unknown,
+            };
+            let flg = self.buf[4];
+            self.block_checksum = flg & FLG_BLOCK_CHECKSUM != 0;
+            self.content_checksum = flg & FLG_CONTENT_CHECKSUM != 0;
+        }
+        if end < len {
+            Stage::Header { pos: end, len }
+        } else {
+            Stage::BlockSize { pos: 0 }
+        }
+    }
+
+    fn block_stage(&self) -> Stage {
+        let size = u32::from_le_bytes([self.buf[0], self.buf[1], self.buf[2], self.buf[3]]);
+        // liblz4 ignores the uncompressed flag when looking for the end mark.
+        let size = size & !BLOCK_UNCOMPRESSED;
+        if size == 0 {
+            if self.content_checksum {
+                Stage::ContentChecksum { remaining: 4 }
+            } else {
+                Stage::End
+            }
+        } else {
+            let checksum = if self.block_checksum { 4 } else { 0 };
+            Stage::Block {
+                remaining: size as usize + checksum,
+            }
+        }
+    }
+}
@@ -0,0 +1,336 @@
+//! Legacy LZ4 frame format, as produced by `lz4 -l` and used for Linux kernel
+//! images: a magic number followed by independently compressed blocks of up to
+//! 8 MB, each prefixed with its compressed size. There is no end mark; the
+//! stream simply ends. As with `lz4 -d`, data following the stream, such as
+//! the uncompressed size appended to kernel images, is left unread.
+
+use super::block::{compress, decompress, CompressionMode, SizePrefix};
+use super::error::Error as LZ4Error;
+use super::liblz4::*;
+use std::cmp;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+
+/// Magic number at the start of a legacy frame.
+const LEGACY_MAGIC: u32 = 0x184C_2102;
+
+/// Uncompressed size of every block but the last one.
+const LEGACY_BLOCK_SIZE: usize = 8 * 1024 * 1024;
+
+#[derive(Debug)]
+pub struct LegacyEncoder<W> {
+    w: W,
+    mode: Option<CompressionMode>,
+    buffer: Vec<u8>,
+}
+
+#[derive(Debug)]
+enum Stage {
+    Magic,
+    BlockSize,
+    Block,
+    End,
+}
+
+#[derive(Debug)]
+pub struct LegacyDecoder<R> {
+    r: R,
+    stage: Stage,
+    // Magic number, block size or compressed block currently being read
+    input: Vec<u8>,
+    filled: usize,
+    output: Vec<u8>,
+    pos: usize,
+    // Decompressed bytes produced so far
+    total: u64,
+}
+
+impl<W: Write> LegacyEncoder<W> {
+    /// Creates a new encoder using the default compression mode, which
+    /// produces the same output as `lz4 -l`.
+    pub fn new(w: W) -> Result<LegacyEncoder<W>> {
+        LegacyEncoder::with_mode(w, None)
+    }
+
+    /// Creates a new encoder compressing each block with the given mode.
+    pub fn with_mode(mut w: W, mode: Option<CompressionMode>) -> Result<LegacyEncoder<W>> {
+        w.write_all(&LEGACY_MAGIC.to_le_bytes())?;
+        Ok(LegacyEncoder {
+            w,
+            mode,
+            buffer: Vec::with_capacity(LEGACY_BLOCK_SIZE),
+        })
+    }
+
+    fn write_block(&mut self) -> Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let compressed = compress(&self.buffer, self.mode, SizePrefix::None)?;
+        self.w.write_all(&(compressed.len() as u32).to_le_bytes())?;
+        self.w.write_all(&compressed)?;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Immutable writer reference.
+    pub fn writer(&self) -> &W {
+        &self.w
+    }
+
+    /// Compresses and writes the last, possibly short, block and returns the
+    /// wrapped writer.
+    pub fn finish(mut self) -> (W, Result<()>) {
+        let result = self.write_block();
+        (self.w, result)
+    }
+}
+
+impl<W: Write> Write for LegacyEncoder<W> {
+    fn write(&mut self, buffer: &[u8]) -> Result<usize> {
+        let mut offset = 0;
+        while offset < buffer.len() {
+            let size = cmp::min(buffer.len() - offset, LEGACY_BLOCK_SIZE - self.buffer.len());
+            self.buffer
+                .extend_from_slice(&buffer[offset..offset + size]);
+            if self.buffer.len() == LEGACY_BLOCK_SIZE {
+                self.write_block()?;
+            }
+            offset += size;
+        }
+        Ok(buffer.len())
+    }
+
+    /// Writes out buffered data as a short block. The result is still a valid
+    /// legacy stream, but no longer identical to the output of `lz4 -l`.
+    fn flush(&mut self) -> Result<()> {
+        self.write_block()?;
+        self.w.flush()
+    }
+}
+
+impl<R: Read> LegacyDecoder<R> {
+    pub fn new(r: R) -> Result<LegacyDecoder<R>> {
+        Ok(LegacyDecoder {
+            r,
+            stage: Stage::Magic,
+            input: vec![0; 4],
+            filled: 0,
+            output: Vec::new(),
+            pos: 0,
+            total: 0,
+        })
+    }
+
+    /// Immutable reader reference.
+    pub fn reader(&self) -> &R {
+        &self.r
+    }
+
+    /// Returns the wrapped reader, along with an error if the end of the
+    /// stream has not been reached.
+    pub fn finish(self) -> (R, Result<()>) {
+        let result = match self.stage {
+            Stage::End => Ok(()),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Finish called before the end of the compressed stream",
+            )),
+        };
+        (self.r, result)
+    }
+
+    /// Reads until `input` is full. Returns `false` if the stream ends first.
+    fn fill(&mut self) -> Result<bool> {
+        while self.filled < self.input.len() {
+            let len = self.r.read(&mut self.input[self.filled..])?;
+            if len == 0 {
+                return Ok(false);
+            }
+            self.filled += len;
+        }
+        Ok(true)
+    }
+
+    fn expect(&mut self, size: usize, stage: Stage) {
+        self.input.resize(size, 0);
+        self.filled = 0;
+        self.stage = stage;
+    }
+}
+
+impl<R: Read> Read for LegacyDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            if self.pos < self.output.len() {
+                let size = cmp::min(buf.len(), self.output.len() - self.pos);
+                buf[..size].copy_from_slice(&self.output[self.pos..self.pos + size]);
+                self.pos += size;
+                return Ok(size);
+            }
+            match self.stage {
+                Stage::Magic => {
+                    if !self.fill()? {
+                        return Err(Error::new(
+                            ErrorKind::UnexpectedEof,
+                            "Stream ended before the legacy frame magic number",
+                        ));
+                    }
+                    if read_u32(&self.input) != LEGACY_MAGIC {
+                        return Err(
+                            LZ4Error::FrameTypeUnknown.with_message("Not an LZ4 legacy frame")
+                        );
+                    }
+                    self.expect(4, Stage::BlockSize);
+                }
+                Stage::BlockSize => {
+                    if !self.fill()? {
+                        if self.filled == 0 {
+                            self.stage = Stage::End;
+                            continue;
+                        }
+                        return Err(Error::new(
+                            ErrorKind::UnexpectedEof,
+                            "Stream ended inside a legacy block size",
+                        ));
+                    }
+                    let size = read_u32(&self.input);
+                    if size == LEGACY_MAGIC {
+                        // Start of another legacy frame
+                        self.expect(4, Stage::BlockSize);
+                        continue;
+                    }
+                    let bound = unsafe { LZ4_compressBound(LEGACY_BLOCK_SIZE as i32) } as u32;
+                    if size > bound {
+                        // Not a block: the stream is followed by other data.
+                        self.stage = Stage::End;
+                        continue;
+                    }
+                    self.expect(size as usize, Stage::Block);
+                }
+                Stage::Block => {
+                    if !self.fill()? {
+                        if self.filled == 0 && self.input.len() as u64 == self.total {
+                            // The last four bytes were the uncompressed size
+                            // appended to a kernel image, not a block size.
+                            self.stage = Stage::End;
+                            continue;
+                        }
+                        return Err(Error::new(
+                            ErrorKind::UnexpectedEof,
+                            "Stream ended inside a legacy block",
+                        ));
+                    }
+                    self.output = decompress(
+                        &self.input,
+                        Some(LEGACY_BLOCK_SIZE as i32),
+                        SizePrefix::None,
+                    )?;
+                    self.pos = 0;
+                    self.total += self.output.len() as u64;
+                    self.expect(4, Stage::BlockSize);
+                }
+                Stage::End => return Ok(0),
+            }
+        }
+    }
+}
+
+fn read_u32(buf: &[u8]) -> u32 {
+    u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]])
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LegacyDecoder, LegacyEncoder, LEGACY_BLOCK_SIZE};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::io::{Cursor, Read, Write};
+
+    #[test]
+    fn test_legacy_empty() {
+        let (buffer, result) = LegacyEncoder::new(Vec::new()).unwrap().finish();
+        result.unwrap();
+        assert_eq!(buffer, vec![0x02, 0x21, 0x4C, 0x18]);
+
+        let mut decoder = LegacyDecoder::new(Cursor::new(buffer)).unwrap();
+        let mut actual = Vec::new();
+        decoder.read_to_end(&mut actual).unwrap();
+        assert!(actual.is_empty());
+        let (_, result) = decoder.finish();
+        result.unwrap();
+    }
+
+    #[test]
+    fn test_legacy_round_trip() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let expected: Vec<u8> = (0..LEGACY_BLOCK_SIZE * 2 + 1000)
+            .map(|_| rng.gen_range(0, 16))
+            .collect();
+        let mut encoder = LegacyEncoder::new(Vec::new()).unwrap();
+        encoder.write_all(&expected).unwrap();
+        let (buffer, result) = encoder.finish();
+        result.unwrap();
+
+        // Two concatenated legacy frames decode as one stream.
+        let mut input = buffer.clone();
+        input.extend_from_slice(&buffer);
+        let mut decoder = LegacyDecoder::new(Cursor::new(input)).unwrap();
+        let mut actual = Vec::new();
+        decoder.read_to_end(&mut actual).unwrap();
+        assert_eq!(actual.len(), expected.len() * 2);
+        assert!(actual[..expected.len()] == expected[..]);
+        assert!(actual[expected.len()..] == expected[..]);
+    }
+
+    #[test]
+    fn test_legacy_matches_cli() {
+        // Produced by `lz4 -l` from the same input.
+        let expected = include_bytes!("../tests/data/legacy.lz4");
+        let input = b"Some data, some more data, and some data again.\n".repeat(20);
+
+        let mut encoder = LegacyEncoder::new(Vec::new()).unwrap();
+        encoder.write_all(&input).unwrap();
+        let (buffer, result) = encoder.finish();
+        result.unwrap();
+        assert_eq!(buffer, &expected[..]);
+
+        let mut decoder = LegacyDecoder::new(Cursor::new(&expected[..])).unwrap();
+        let mut actual = Vec::new();
+        decoder.read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, input);
+    }
+
+    #[test]
+    fn test_legacy_appended_size() {
+        let input = b"Some data, some more data, and some data again.\n".repeat(20);
+        let mut encoder = LegacyEncoder::new(Vec::new()).unwrap();
+        encoder.write_all(&input).unwrap();
+        let (buffer, result) = encoder.finish();
+        result.unwrap();
+
+        // Kernel images end with their uncompressed size, which can be taken
+        // for a block size either larger or smaller than the block limit.
+        for size in &[input.len() as u32, 64 * 1024 * 1024] {
+            let mut image = buffer.clone();
+            image.extend_from_slice(&size.to_le_bytes());
+            let mut decoder = LegacyDecoder::new(Cursor::new(image)).unwrap();
+            let mut actual = Vec::new();
+            decoder.read_to_end(&mut actual).unwrap();
+            assert_eq!(actual, input);
+            let (_, result) = decoder.finish();
+            result.unwrap();
+        }
+    }
+
+    #[test]
+    fn test_legacy_truncated() {
+        let mut encoder = LegacyEncoder::new(Vec::new()).unwrap();
+        encoder.write_all(b"Some data").unwrap();
+        let (mut buffer, result) = encoder.finish();
+        result.unwrap();
+        buffer.pop();
+
+        let mut decoder = LegacyDecoder::new(Cursor::new(buffer)).unwrap();
+        assert!(decoder.read_to_end(&mut Vec::new()).is_err());
+    }
+}
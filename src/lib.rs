@@ -5,16 +5,47 @@ pub mod liblz4;
 
 mod decoder;
 mod encoder;
+mod error;
+mod frame;
+mod legacy;
+#[cfg(any(feature = "tokio", feature = "futures-io"))]
+mod nonblocking;
+mod parallel;
 
 pub mod block;
 
 pub use crate::decoder::Decoder;
+pub use crate::decoder::DecoderBuilder;
+pub use crate::decoder::DecoderStats;
+pub use crate::decoder::FrameInfo;
+pub use crate::decoder::SkippableFrame;
+pub use crate::encoder::write_skippable_frame;
+pub use crate::encoder::AutoFinishEncoder;
 pub use crate::encoder::Encoder;
 pub use crate::encoder::EncoderBuilder;
+pub use crate::encoder::EncoderStats;
+pub use crate::error::Error;
+pub use crate::frame::dictionary_id;
+pub use crate::legacy::LegacyDecoder;
+pub use crate::legacy::LegacyEncoder;
 pub use crate::liblz4::version;
+pub use crate::liblz4::BlockChecksum;
 pub use crate::liblz4::BlockMode;
 pub use crate::liblz4::BlockSize;
 pub use crate::liblz4::ContentChecksum;
+pub use crate::parallel::ParallelDecoder;
+pub use crate::parallel::ParallelDecoderBuilder;
+pub use crate::parallel::ParallelEncoder;
+pub use crate::parallel::ParallelEncoderBuilder;
+
+#[cfg(feature = "tokio")]
+pub use crate::nonblocking::AsyncDecoder;
+#[cfg(feature = "tokio")]
+pub use crate::nonblocking::AsyncEncoder;
+#[cfg(feature = "futures-io")]
+pub use crate::nonblocking::FuturesDecoder;
+#[cfg(feature = "futures-io")]
+pub use crate::nonblocking::FuturesEncoder;
 
 #[cfg(not(all(
     target_arch = "wasm32",
@@ -1,43 +1,18 @@
+use super::error;
 use std::ffi::CStr;
-use std::fmt::Display;
-use std::fmt::Formatter;
 use std::io::Error;
-use std::io::ErrorKind;
-use std::str;
 
 pub use lz4_sys::*;
 
-#[derive(Debug)]
-pub struct LZ4Error(String);
-
-impl Display for LZ4Error {
-    fn fmt(&self, f: &mut Formatter) -> Result<(), ::std::fmt::Error> {
-        write!(f, "LZ4 error: {}", &self.0)
-    }
-}
-
-impl ::std::error::Error for LZ4Error {
-    fn description(&self) -> &str {
-        &self.0
-    }
-
-    fn cause(&self) -> Option<&dyn (::std::error::Error)> {
-        None
-    }
-}
+#[allow(deprecated)]
+pub use super::error::LZ4Error;
 
 pub fn check_error(code: LZ4FErrorCode) -> Result<usize, Error> {
     unsafe {
         if LZ4F_isError(code) != 0 {
-            let error_name = LZ4F_getErrorName(code);
-            return Err(Error::new(
-                ErrorKind::Other,
-                LZ4Error(
-                    str::from_utf8(CStr::from_ptr(error_name).to_bytes())
-                        .unwrap()
-                        .to_string(),
-                ),
-            ));
+            let error = error::Error::from_code(LZ4F_getErrorCode(code));
+            let name = CStr::from_ptr(LZ4F_getErrorName(code)).to_string_lossy();
+            return Err(error.with_message(format!("{} ({})", error, name)));
         }
     }
     Ok(code as usize)
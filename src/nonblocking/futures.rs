@@ -0,0 +1,235 @@
+//! Encoder and decoder for the `AsyncWrite` and `AsyncRead` of `futures-io`,
+//! as used by async-std and smol.
+
+use super::{Bridge, PollFn, PollRead, PollWrite};
+use crate::decoder::{Decoder, DecoderBuilder, DecoderStats, FrameInfo, SkippableFrame};
+use crate::encoder::{Encoder, EncoderBuilder, EncoderStats};
+use futures_io03::{AsyncRead, AsyncWrite};
+use std::io::Result;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+#[derive(Debug)]
+struct Futures<T>(T);
+
+impl<T: AsyncRead + Unpin> PollRead for Futures<T> {
+    fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<usize>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> PollWrite for Futures<T> {
+    fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+}
+
+/// `Encoder` writing to a `futures-io` `AsyncWrite`, built with
+/// `EncoderBuilder::build_futures()`. It behaves as `AsyncEncoder`, closing
+/// it finishes the frame before closing the wrapped writer.
+#[derive(Debug)]
+pub struct FuturesEncoder<W> {
+    e: Encoder<Bridge<Futures<W>>>,
+}
+
+/// `Decoder` reading from a `futures-io` `AsyncRead`, built with
+/// `DecoderBuilder::build_futures()`.
+#[derive(Debug)]
+pub struct FuturesDecoder<R> {
+    d: Decoder<Bridge<Futures<R>>>,
+}
+
+impl EncoderBuilder {
+    /// Builds an encoder writing to a `futures-io` `AsyncWrite`. The frame
+    /// header is written along with the first data.
+    pub fn build_futures<W: AsyncWrite + Unpin>(&self, w: W) -> Result<FuturesEncoder<W>> {
+        Ok(FuturesEncoder {
+            e: self.build_pending(Bridge::new(Futures(w)))?,
+        })
+    }
+}
+
+impl DecoderBuilder {
+    /// Builds a decoder reading from a `futures-io` `AsyncRead`.
+    pub fn build_futures<R: AsyncRead + Unpin>(&self, r: R) -> Result<FuturesDecoder<R>> {
+        Ok(FuturesDecoder {
+            d: self.build(Bridge::new(Futures(r)))?,
+        })
+    }
+}
+
+impl<W: AsyncWrite + Unpin> FuturesEncoder<W> {
+    /// Immutable writer reference.
+    pub fn writer(&self) -> &W {
+        &self.e.writer().inner.0
+    }
+
+    /// Mutable writer reference, see `Encoder::get_mut()`.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.e.get_mut().inner.0
+    }
+
+    /// Sizes of the frame written so far.
+    pub fn stats(&self) -> EncoderStats {
+        self.e.stats()
+    }
+
+    /// Writes the end of the frame and flushes the wrapped writer, keeping it
+    /// open. Calling it again does nothing, and writing more data is an error.
+    pub fn poll_finish(&mut self, cx: &mut Context<'_>) -> Poll<Result<EncoderStats>> {
+        self.e.poll_finish(cx)
+    }
+
+    /// Finishes the frame, see `poll_finish()`, and returns the wrapped writer
+    /// along with the final statistics.
+    pub async fn finish(mut self) -> (W, Result<EncoderStats>) {
+        let result = PollFn(|cx: &mut Context<'_>| self.poll_finish(cx)).await;
+        (self.e.into_writer().inner.0, result)
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for FuturesEncoder<W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        self.get_mut().e.poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().e.poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        // Closing the wrapped writer flushes it.
+        match this.e.poll_end(cx) {
+            Poll::Ready(Ok(_)) => Pin::new(this.get_mut()).poll_close(cx),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> FuturesDecoder<R> {
+    /// Creates a decoder reading a single frame, see `Decoder::new()`.
+    pub fn new(r: R) -> Result<FuturesDecoder<R>> {
+        DecoderBuilder::new().build_futures(r)
+    }
+
+    /// Immutable reader reference.
+    pub fn reader(&self) -> &R {
+        &self.d.reader().inner.0
+    }
+
+    /// Returns the parameters of the frame being decoded, see
+    /// `Decoder::frame_info()`.
+    pub async fn frame_info(&mut self) -> Result<FrameInfo> {
+        let d = &mut self.d;
+        PollFn(|cx: &mut Context<'_>| d.poll_frame_info(cx)).await
+    }
+
+    /// Returns the skippable frames collected so far, see
+    /// `Decoder::take_skippable_frames()`.
+    pub fn take_skippable_frames(&mut self) -> Vec<SkippableFrame> {
+        self.d.take_skippable_frames()
+    }
+
+    /// Returns the wrapped reader along with totals for the decoded frames,
+    /// see `Decoder::finish()`.
+    pub fn finish(self) -> (R, Result<DecoderStats>) {
+        let (r, result) = self.d.finish();
+        (r.inner.0, result)
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for FuturesDecoder<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize>> {
+        self.get_mut().d.poll_read(cx, buf)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::test::{block_on, data, Trickle};
+    use super::super::PollFn;
+    use super::{FuturesDecoder, FuturesEncoder};
+    use crate::{Decoder, EncoderBuilder};
+    use futures_io03::{AsyncRead, AsyncWrite};
+    use std::io::{ErrorKind, Read, Result, Write};
+    use std::pin::Pin;
+    use std::task::Context;
+
+    async fn write_all<W: AsyncWrite + Unpin>(w: &mut W, mut buf: &[u8]) -> Result<()> {
+        while !buf.is_empty() {
+            match PollFn(|cx: &mut Context<'_>| Pin::new(&mut *w).poll_write(cx, buf)).await? {
+                0 => return Err(ErrorKind::WriteZero.into()),
+                len => buf = &buf[len..],
+            }
+        }
+        Ok(())
+    }
+
+    async fn read_to_end<R: AsyncRead + Unpin>(r: &mut R, out: &mut Vec<u8>) -> Result<()> {
+        let mut buf = [0; 1024];
+        loop {
+            match PollFn(|cx: &mut Context<'_>| Pin::new(&mut *r).poll_read(cx, &mut buf)).await? {
+                0 => return Ok(()),
+                len => out.extend_from_slice(&buf[..len]),
+            }
+        }
+    }
+
+    #[test]
+    fn test_futures_encoder() {
+        let data = data();
+        block_on(async {
+            let mut encoder = EncoderBuilder::new()
+                .build_futures(Trickle::new(Vec::new()))
+                .unwrap();
+            write_all(&mut encoder, &data).await.unwrap();
+            PollFn(|cx: &mut Context<'_>| Pin::new(&mut encoder).poll_close(cx))
+                .await
+                .unwrap();
+            assert!(write_all(&mut encoder, b"More data").await.is_err());
+            let stats = encoder.stats();
+            assert_eq!(stats.bytes_in, data.len() as u64);
+            assert_eq!(stats.bytes_out, encoder.writer().inner.len() as u64);
+
+            let mut decoded = Vec::new();
+            let mut decoder = Decoder::new(&encoder.writer().inner[..]).unwrap();
+            decoder.read_to_end(&mut decoded).unwrap();
+            assert_eq!(decoded, data);
+
+            let encoder: FuturesEncoder<Vec<u8>> = EncoderBuilder::new()
+                .content_size(10)
+                .build_futures(Vec::new())
+                .unwrap();
+            let (_, result) = encoder.finish().await;
+            assert!(result.is_err());
+        });
+    }
+
+    #[test]
+    fn test_futures_decoder() {
+        let data = data();
+        let mut encoder = EncoderBuilder::new().build(Vec::new()).unwrap();
+        encoder.write_all(&data).unwrap();
+        let (encoded, result) = encoder.finish();
+        result.unwrap();
+        block_on(async {
+            let mut decoder = FuturesDecoder::new(Trickle::new(&encoded[..])).unwrap();
+            assert_eq!(decoder.frame_info().await.unwrap().content_size, None);
+            let mut decoded = Vec::new();
+            read_to_end(&mut decoder, &mut decoded).await.unwrap();
+            assert_eq!(decoded, data);
+            let (_, result) = decoder.finish();
+            assert_eq!(result.unwrap().bytes_out, data.len() as u64);
+        });
+    }
+}
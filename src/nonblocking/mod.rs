@@ -0,0 +1,307 @@
+//! Non-blocking encoders and decoders. They drive the same `Encoder` and
+//! `Decoder` as the blocking API, over a bridge which turns `Poll::Pending`
+//! from the wrapped reader or writer into an `ErrorKind::WouldBlock` error.
+//! Both keep their state across such errors, so the call can be repeated once
+//! the task is woken up, and the error is turned back into `Poll::Pending`.
+
+#[cfg(feature = "futures-io")]
+mod futures;
+#[cfg(feature = "tokio")]
+mod tokio;
+
+#[cfg(feature = "futures-io")]
+pub use self::futures::{FuturesDecoder, FuturesEncoder};
+#[cfg(feature = "tokio")]
+pub use self::tokio::{AsyncDecoder, AsyncEncoder};
+
+use crate::decoder::{Decoder, FrameInfo};
+use crate::encoder::{Encoder, EncoderStats};
+use std::future::Future;
+use std::io::{ErrorKind, Read, Result, Write};
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+
+/// Non-blocking reader, as provided by each async runtime.
+pub(crate) trait PollRead {
+    fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<usize>>;
+}
+
+/// Non-blocking writer, as provided by each async runtime.
+pub(crate) trait PollWrite {
+    fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>>;
+    fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>>;
+}
+
+/// Blocking view of a non-blocking reader or writer, for the duration of a
+/// poll of the task given to `register()`.
+#[derive(Debug)]
+pub(crate) struct Bridge<T> {
+    pub(crate) inner: T,
+    waker: Option<Waker>,
+}
+
+impl<T> Bridge<T> {
+    pub(crate) fn new(inner: T) -> Self {
+        Bridge { inner, waker: None }
+    }
+
+    /// Remembers the task to wake up when the wrapped reader or writer is ready.
+    pub(crate) fn register(&mut self, cx: &Context<'_>) {
+        match self.waker {
+            Some(ref waker) if waker.will_wake(cx.waker()) => {}
+            _ => self.waker = Some(cx.waker().clone()),
+        }
+    }
+
+    fn poll<R, F>(&mut self, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut T, &mut Context<'_>) -> Poll<Result<R>>,
+    {
+        let waker = self.waker.as_ref().expect("Bridge used outside of a poll");
+        match f(&mut self.inner, &mut Context::from_waker(waker)) {
+            Poll::Ready(result) => result,
+            Poll::Pending => Err(ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+impl<T: PollRead> Read for Bridge<T> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.poll(|inner, cx| inner.poll_read(cx, buf))
+    }
+}
+
+impl<T: PollWrite> Write for Bridge<T> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.poll(|inner, cx| inner.poll_write(cx, buf))
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.poll(|inner, cx| inner.poll_flush(cx))
+    }
+}
+
+/// Turns the result of a call made through a `Bridge` back into a poll.
+pub(crate) fn poll_io<R>(result: Result<R>) -> Poll<Result<R>> {
+    match result {
+        Err(ref e) if e.kind() == ErrorKind::WouldBlock => Poll::Pending,
+        result => Poll::Ready(result),
+    }
+}
+
+/// Future polling a closure until it is ready.
+pub(crate) struct PollFn<F>(pub(crate) F);
+
+impl<R, F: FnMut(&mut Context<'_>) -> Poll<R> + Unpin> Future for PollFn<F> {
+    type Output = R;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<R> {
+        (self.0)(cx)
+    }
+}
+
+/// Polls shared by the encoders of each runtime.
+impl<T: PollWrite> Encoder<Bridge<T>> {
+    pub(crate) fn poll_write(&mut self, cx: &Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        self.get_mut().register(cx);
+        poll_io(self.write(buf))
+    }
+
+    pub(crate) fn poll_flush(&mut self, cx: &Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().register(cx);
+        poll_io(self.flush())
+    }
+
+    /// Writes out the end of the frame, without flushing the wrapped writer.
+    pub(crate) fn poll_end(&mut self, cx: &Context<'_>) -> Poll<Result<EncoderStats>> {
+        self.get_mut().register(cx);
+        poll_io(self.try_finish())
+    }
+
+    /// Writes out the end of the frame and flushes the wrapped writer.
+    pub(crate) fn poll_finish(&mut self, cx: &Context<'_>) -> Poll<Result<EncoderStats>> {
+        self.get_mut().register(cx);
+        poll_io(
+            self.try_finish()
+                .and_then(|stats| self.flush().map(|()| stats)),
+        )
+    }
+}
+
+/// Polls shared by the decoders of each runtime.
+impl<T: PollRead> Decoder<Bridge<T>> {
+    pub(crate) fn poll_read(&mut self, cx: &Context<'_>, buf: &mut [u8]) -> Poll<Result<usize>> {
+        self.reader_mut().register(cx);
+        poll_io(self.read(buf))
+    }
+
+    pub(crate) fn poll_frame_info(&mut self, cx: &Context<'_>) -> Poll<Result<FrameInfo>> {
+        self.reader_mut().register(cx);
+        poll_io(self.frame_info())
+    }
+}
+
+/// Helpers shared by the tests of each runtime.
+#[cfg(test)]
+pub(crate) mod test {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::future::Future;
+    use std::io::{Read, Result, Write};
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::thread::{self, Thread};
+
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    /// Runs a future on the current thread, which sleeps until it is woken up.
+    pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    /// Returns `Poll::Pending` on every other call, and moves at most a few
+    /// bytes at a time otherwise.
+    pub(crate) struct Trickle<T> {
+        pub(crate) inner: T,
+        pending: bool,
+        chunk: usize,
+    }
+
+    impl<T> Trickle<T> {
+        pub(crate) fn new(inner: T) -> Self {
+            Trickle {
+                inner,
+                pending: false,
+                chunk: 1,
+            }
+        }
+
+        fn ready(&mut self, cx: &mut Context<'_>) -> Option<usize> {
+            self.pending = !self.pending;
+            if self.pending {
+                cx.waker().wake_by_ref();
+                return None;
+            }
+            self.chunk = self.chunk % 7 + 1;
+            Some(self.chunk)
+        }
+    }
+
+    impl<T: Read> super::PollRead for Trickle<T> {
+        fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<usize>> {
+            match self.ready(cx) {
+                Some(chunk) => {
+                    let len = chunk.min(buf.len());
+                    Poll::Ready(self.inner.read(&mut buf[..len]))
+                }
+                None => Poll::Pending,
+            }
+        }
+    }
+
+    impl<T: Write> super::PollWrite for Trickle<T> {
+        fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+            match self.ready(cx) {
+                Some(chunk) => Poll::Ready(self.inner.write(&buf[..chunk.min(buf.len())])),
+                None => Poll::Pending,
+            }
+        }
+
+        fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+            match self.ready(cx) {
+                Some(_) => Poll::Ready(self.inner.flush()),
+                None => Poll::Pending,
+            }
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    impl<T: Read + Unpin> tokio1::io::AsyncRead for Trickle<T> {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut tokio1::io::ReadBuf<'_>,
+        ) -> Poll<Result<()>> {
+            super::PollRead::poll_read(self.get_mut(), cx, buf.initialize_unfilled())
+                .map_ok(|len| buf.advance(len))
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    impl<T: Write + Unpin> tokio1::io::AsyncWrite for Trickle<T> {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<Result<usize>> {
+            super::PollWrite::poll_write(self.get_mut(), cx, buf)
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+            super::PollWrite::poll_flush(self.get_mut(), cx)
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+            super::PollWrite::poll_flush(self.get_mut(), cx)
+        }
+    }
+
+    #[cfg(feature = "futures-io")]
+    impl<T: Read + Unpin> futures_io03::AsyncRead for Trickle<T> {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<Result<usize>> {
+            super::PollRead::poll_read(self.get_mut(), cx, buf)
+        }
+    }
+
+    #[cfg(feature = "futures-io")]
+    impl<T: Write + Unpin> futures_io03::AsyncWrite for Trickle<T> {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<Result<usize>> {
+            super::PollWrite::poll_write(self.get_mut(), cx, buf)
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+            super::PollWrite::poll_flush(self.get_mut(), cx)
+        }
+
+        fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+            super::PollWrite::poll_flush(self.get_mut(), cx)
+        }
+    }
+
+    /// Alternates compressible runs of text with random bytes.
+    pub(crate) fn data() -> Vec<u8> {
+        let mut rng = StdRng::seed_from_u64(42);
+        (0..100_000)
+            .map(|i| {
+                if i % 1000 < 500 {
+                    b"async"[i % 5]
+                } else {
+                    rng.gen()
+                }
+            })
+            .collect()
+    }
+}
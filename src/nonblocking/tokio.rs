@@ -0,0 +1,295 @@
+//! Encoder and decoder for tokio's `AsyncWrite` and `AsyncRead`.
+
+use super::{Bridge, PollFn, PollRead, PollWrite};
+use crate::decoder::{Decoder, DecoderBuilder, DecoderStats, FrameInfo, SkippableFrame};
+use crate::encoder::{Encoder, EncoderBuilder, EncoderStats};
+use std::io::Result;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio1::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+#[derive(Debug)]
+struct Tokio<T>(T);
+
+impl<T: AsyncRead + Unpin> PollRead for Tokio<T> {
+    fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<usize>> {
+        let mut buf = ReadBuf::new(buf);
+        Pin::new(&mut self.0)
+            .poll_read(cx, &mut buf)
+            .map_ok(|()| buf.filled().len())
+    }
+}
+
+impl<T: AsyncWrite + Unpin> PollWrite for Tokio<T> {
+    fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+}
+
+/// `Encoder` writing to a tokio `AsyncWrite`, built with
+/// `EncoderBuilder::build_async()`. Shutting it down finishes the frame before
+/// shutting down the wrapped writer; `finish()` hands the writer back instead.
+/// Compressed data is written out as the wrapped writer accepts it, so a write
+/// may return before all of its output has been written.
+#[derive(Debug)]
+pub struct AsyncEncoder<W> {
+    e: Encoder<Bridge<Tokio<W>>>,
+}
+
+/// `Decoder` reading from a tokio `AsyncRead`, built with
+/// `DecoderBuilder::build_async()`.
+#[derive(Debug)]
+pub struct AsyncDecoder<R> {
+    d: Decoder<Bridge<Tokio<R>>>,
+}
+
+impl EncoderBuilder {
+    /// Builds an encoder writing to a tokio `AsyncWrite`. The frame header is
+    /// written along with the first data.
+    pub fn build_async<W: AsyncWrite + Unpin>(&self, w: W) -> Result<AsyncEncoder<W>> {
+        Ok(AsyncEncoder {
+            e: self.build_pending(Bridge::new(Tokio(w)))?,
+        })
+    }
+}
+
+impl DecoderBuilder {
+    /// Builds a decoder reading from a tokio `AsyncRead`.
+    pub fn build_async<R: AsyncRead + Unpin>(&self, r: R) -> Result<AsyncDecoder<R>> {
+        Ok(AsyncDecoder {
+            d: self.build(Bridge::new(Tokio(r)))?,
+        })
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncEncoder<W> {
+    /// Immutable writer reference.
+    pub fn writer(&self) -> &W {
+        &self.e.writer().inner.0
+    }
+
+    /// Mutable writer reference, see `Encoder::get_mut()`.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.e.get_mut().inner.0
+    }
+
+    /// Sizes of the frame written so far.
+    pub fn stats(&self) -> EncoderStats {
+        self.e.stats()
+    }
+
+    /// Writes the end of the frame and flushes the wrapped writer, keeping it
+    /// open. Calling it again does nothing, and writing more data is an error.
+    pub fn poll_finish(&mut self, cx: &mut Context<'_>) -> Poll<Result<EncoderStats>> {
+        self.e.poll_finish(cx)
+    }
+
+    /// Finishes the frame, see `poll_finish()`, and returns the wrapped writer
+    /// along with the final statistics.
+    pub async fn finish(mut self) -> (W, Result<EncoderStats>) {
+        let result = PollFn(|cx: &mut Context<'_>| self.poll_finish(cx)).await;
+        (self.e.into_writer().inner.0, result)
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for AsyncEncoder<W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        self.get_mut().e.poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().e.poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        // Shutting down the wrapped writer flushes it.
+        match this.e.poll_end(cx) {
+            Poll::Ready(Ok(_)) => Pin::new(this.get_mut()).poll_shutdown(cx),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncDecoder<R> {
+    /// Creates a decoder reading a single frame, see `Decoder::new()`.
+    pub fn new(r: R) -> Result<AsyncDecoder<R>> {
+        DecoderBuilder::new().build_async(r)
+    }
+
+    /// Immutable reader reference.
+    pub fn reader(&self) -> &R {
+        &self.d.reader().inner.0
+    }
+
+    /// Returns the parameters of the frame being decoded, see
+    /// `Decoder::frame_info()`.
+    pub async fn frame_info(&mut self) -> Result<FrameInfo> {
+        let d = &mut self.d;
+        PollFn(|cx: &mut Context<'_>| d.poll_frame_info(cx)).await
+    }
+
+    /// Returns the skippable frames collected so far, see
+    /// `Decoder::take_skippable_frames()`.
+    pub fn take_skippable_frames(&mut self) -> Vec<SkippableFrame> {
+        self.d.take_skippable_frames()
+    }
+
+    /// Returns the wrapped reader along with totals for the decoded frames,
+    /// see `Decoder::finish()`.
+    pub fn finish(self) -> (R, Result<DecoderStats>) {
+        let (r, result) = self.d.finish();
+        (r.inner.0, result)
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for AsyncDecoder<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        self.get_mut()
+            .d
+            .poll_read(cx, buf.initialize_unfilled())
+            .map_ok(|len| buf.advance(len))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::test::{block_on, data, Trickle};
+    use super::super::PollFn;
+    use super::{AsyncDecoder, AsyncEncoder};
+    use crate::{Decoder, DecoderBuilder, EncoderBuilder};
+    use std::io::{ErrorKind, Read, Result, Write};
+    use std::pin::Pin;
+    use std::task::Context;
+    use tokio1::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+    fn encode(data: &[u8]) -> Vec<u8> {
+        let mut encoder = EncoderBuilder::new().build(Vec::new()).unwrap();
+        encoder.write_all(data).unwrap();
+        let (buffer, result) = encoder.finish();
+        result.unwrap();
+        buffer
+    }
+
+    async fn write_all<W: AsyncWrite + Unpin>(w: &mut W, mut buf: &[u8]) -> Result<()> {
+        while !buf.is_empty() {
+            match PollFn(|cx: &mut Context<'_>| Pin::new(&mut *w).poll_write(cx, buf)).await? {
+                0 => return Err(ErrorKind::WriteZero.into()),
+                len => buf = &buf[len..],
+            }
+        }
+        Ok(())
+    }
+
+    async fn read_to_end<R: AsyncRead + Unpin>(r: &mut R, out: &mut Vec<u8>) -> Result<()> {
+        let mut buf = [0; 1024];
+        loop {
+            let mut buf = ReadBuf::new(&mut buf);
+            PollFn(|cx: &mut Context<'_>| Pin::new(&mut *r).poll_read(cx, &mut buf)).await?;
+            if buf.filled().is_empty() {
+                return Ok(());
+            }
+            out.extend_from_slice(buf.filled());
+        }
+    }
+
+    #[test]
+    fn test_async_encoder() {
+        let data = data();
+        block_on(async {
+            let mut encoder = EncoderBuilder::new()
+                .build_async(Trickle::new(Vec::new()))
+                .unwrap();
+            for chunk in data.chunks(30_000) {
+                write_all(&mut encoder, chunk).await.unwrap();
+            }
+            PollFn(|cx: &mut Context<'_>| Pin::new(&mut encoder).poll_flush(cx))
+                .await
+                .unwrap();
+            assert!(!encoder.writer().inner.is_empty());
+            let (writer, result) = encoder.finish().await;
+            let stats = result.unwrap();
+            assert_eq!(stats.bytes_in, data.len() as u64);
+            assert_eq!(stats.bytes_out, writer.inner.len() as u64);
+
+            let mut decoded = Vec::new();
+            let mut decoder = Decoder::new(&writer.inner[..]).unwrap();
+            decoder.read_to_end(&mut decoded).unwrap();
+            assert_eq!(decoded, data);
+        });
+    }
+
+    #[test]
+    fn test_async_encoder_shutdown() {
+        let data = data();
+        block_on(async {
+            let mut encoder = EncoderBuilder::new()
+                .build_async(Trickle::new(Vec::new()))
+                .unwrap();
+            write_all(&mut encoder, &data).await.unwrap();
+            PollFn(|cx: &mut Context<'_>| Pin::new(&mut encoder).poll_shutdown(cx))
+                .await
+                .unwrap();
+            assert!(write_all(&mut encoder, b"More data").await.is_err());
+            assert_eq!(encoder.writer().inner, encode(&data));
+
+            let encoder = EncoderBuilder::new()
+                .content_size(10)
+                .build_async(Vec::new())
+                .unwrap();
+            let (_, result) = encoder.finish().await;
+            assert!(result.is_err());
+        });
+    }
+
+    #[test]
+    fn test_async_decoder() {
+        let data = data();
+        let encoded = encode(&data);
+        block_on(async {
+            let mut decoder = AsyncDecoder::new(Trickle::new(&encoded[..])).unwrap();
+            assert_eq!(decoder.frame_info().await.unwrap().content_size, None);
+            let mut decoded = Vec::new();
+            read_to_end(&mut decoder, &mut decoded).await.unwrap();
+            assert_eq!(decoded, data);
+            let (_, result) = decoder.finish();
+            assert_eq!(result.unwrap().bytes_out, data.len() as u64);
+
+            let twice = [&encoded[..], &encoded[..]].concat();
+            let mut decoder = DecoderBuilder::new()
+                .multiple_frames(true)
+                .build_async(Trickle::new(&twice[..]))
+                .unwrap();
+            let mut decoded = Vec::new();
+            read_to_end(&mut decoder, &mut decoded).await.unwrap();
+            assert_eq!(decoded, [&data[..], &data[..]].concat());
+            let (_, result) = decoder.finish();
+            assert_eq!(result.unwrap().frames, 2);
+
+            let mut decoder = AsyncDecoder::new(Trickle::new(&encoded[..100])).unwrap();
+            let err = read_to_end(&mut decoder, &mut Vec::new())
+                .await
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+            let (_, result) = decoder.finish();
+            assert_eq!(result.unwrap_err().kind(), ErrorKind::UnexpectedEof);
+        });
+    }
+
+    #[test]
+    fn test_async_send() {
+        fn check_send<S: Send>(_: &S) {}
+        let encoder: AsyncEncoder<Vec<u8>> = EncoderBuilder::new().build_async(Vec::new()).unwrap();
+        check_send(&encoder);
+        check_send(&AsyncDecoder::new(&b""[..]).unwrap());
+    }
+}
@@ -0,0 +1,474 @@
+use super::WorkerPool;
+use crate::c_char;
+use crate::decoder::{DecoderContext, DecoderStats};
+use crate::error::Error as LZ4Error;
+use crate::frame::{
+    header_size, ContentHasher, BLOCK_UNCOMPRESSED, HEADER_PREFIX_SIZE, SKIPPABLE_HEADER_SIZE,
+    SKIPPABLE_MAGIC, SKIPPABLE_MAGIC_MASK,
+};
+use crate::liblz4::*;
+use crate::size_t;
+use std::cmp;
+use std::io::{self, Error, ErrorKind, Read, Result};
+
+#[derive(Clone, Debug)]
+pub struct ParallelDecoderBuilder {
+    workers: usize,
+    // 0 == twice the number of workers
+    max_in_flight: usize,
+}
+
+/// A block read from the frame, to be checked and decompressed by a worker.
+#[derive(Debug)]
+struct Job {
+    index: u64,
+    data: Vec<u8>,
+    compressed: bool,
+    checksum: Option<u32>,
+    max_size: usize,
+}
+
+/// Where we are in the frame, as far as reading input goes.
+#[derive(Debug, PartialEq)]
+enum Stage {
+    Header,
+    Blocks,
+    // The end mark has been read; blocks may still be in flight.
+    Trailer,
+    End,
+}
+
+#[derive(Debug)]
+pub struct ParallelDecoder<R> {
+    // Only used to parse and validate the frame header
+    c: DecoderContext,
+    pool: WorkerPool<Job, Vec<u8>>,
+    r: R,
+    stage: Stage,
+    max_in_flight: usize,
+    max_block_size: usize,
+    block_checksum: bool,
+    hasher: Option<ContentHasher>,
+    content_size: u64,
+    content_checksum: Option<u32>,
+    blocks: u64,
+    bytes_in: u64,
+    decompressed: u64,
+    output: Vec<u8>,
+    pos: usize,
+}
+
+impl Default for ParallelDecoderBuilder {
+    fn default() -> Self {
+        ParallelDecoderBuilder::new()
+    }
+}
+
+impl ParallelDecoderBuilder {
+    pub fn new() -> Self {
+        ParallelDecoderBuilder {
+            workers: 4,
+            max_in_flight: 0,
+        }
+    }
+
+    /// Number of worker threads decompressing blocks, 4 by default.
+    pub fn workers(&mut self, workers: usize) -> &mut Self {
+        self.workers = workers;
+        self
+    }
+
+    /// Maximum number of blocks read ahead of the one being returned, which
+    /// bounds memory use to about twice that many blocks. Defaults to twice
+    /// the number of workers.
+    pub fn max_in_flight(&mut self, blocks: usize) -> &mut Self {
+        self.max_in_flight = blocks;
+        self
+    }
+
+    pub fn build<R: Read>(&self, r: R) -> Result<ParallelDecoder<R>> {
+        if self.workers == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "ParallelDecoder needs at least one worker",
+            ));
+        }
+        let pool = WorkerPool::new(self.workers, || Ok(decompress_block))?;
+        Ok(ParallelDecoder {
+            c: DecoderContext::new()?,
+            pool,
+            r,
+            stage: Stage::Header,
+            max_in_flight: match self.max_in_flight {
+                0 => self.workers * 2,
+                blocks => blocks,
+            },
+            max_block_size: 0,
+            block_checksum: false,
+            hasher: None,
+            content_size: 0,
+            content_checksum: None,
+            blocks: 0,
+            bytes_in: 0,
+            decompressed: 0,
+            output: Vec::new(),
+            pos: 0,
+        })
+    }
+}
+
+impl<R: Read> ParallelDecoder<R> {
+    /// Creates a decoder reading a single frame with the default settings.
+    /// Frames using `BlockMode::Linked` or a dictionary are rejected when the
+    /// header is read.
+    pub fn new(r: R) -> Result<ParallelDecoder<R>> {
+        ParallelDecoderBuilder::new().build(r)
+    }
+
+    /// Immutable reader reference.
+    pub fn reader(&self) -> &R {
+        &self.r
+    }
+
+    /// Returns the wrapped reader along with totals for the decoded frame, as
+    /// `Decoder::finish()`, or an error if the end of the frame has not been
+    /// reached.
+    pub fn finish(self) -> (R, Result<DecoderStats>) {
+        let result = match self.stage {
+            Stage::End => Ok(DecoderStats {
+                bytes_in: self.bytes_in,
+                bytes_out: self.decompressed,
+                frames: 1,
+                content_checksum: self.content_checksum,
+            }),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Finish called before the end of the compressed stream",
+            )),
+        };
+        (self.r, result)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        self.r.read_exact(buf).map_err(|e| match e.kind() {
+            ErrorKind::UnexpectedEof => {
+                Error::new(ErrorKind::UnexpectedEof, "Stream ended inside an LZ4 frame")
+            }
+            _ => e,
+        })?;
+        self.bytes_in += buf.len() as u64;
+        Ok(())
+    }
+
+    /// Skips leading skippable frames, then parses the frame header.
+    fn read_header(&mut self) -> Result<()> {
+        let mut header = [0u8; 19];
+        loop {
+            self.read_exact(&mut header[..4])?;
+            let magic = read_u32(&header);
+            if magic & SKIPPABLE_MAGIC_MASK != SKIPPABLE_MAGIC {
+                break;
+            }
+            self.read_exact(&mut header[4..SKIPPABLE_HEADER_SIZE])?;
+            let size = read_u32(&header[4..]) as u64;
+            if io::copy(&mut (&mut self.r).take(size), &mut io::sink())? < size {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "Stream ended inside a skippable frame",
+                ));
+            }
+            self.bytes_in += size;
+        }
+        self.read_exact(&mut header[4..HEADER_PREFIX_SIZE])?;
+        let size = match header_size(&header) {
+            Some(size) => size,
+            None => return Err(LZ4Error::FrameTypeUnknown.into()),
+        };
+        self.read_exact(&mut header[HEADER_PREFIX_SIZE..size])?;
+        let mut info = LZ4FFrameInfo {
+            block_size_id: BlockSize::Default,
+            block_mode: BlockMode::Linked,
+            content_checksum_flag: ContentChecksum::NoChecksum,
+            frame_type: FrameType::Frame,
+            content_size: 0,
+            dict_id: 0,
+            block_checksum_flag: BlockChecksum::NoBlockChecksum,
+        };
+        let mut src_size = size as size_t;
+        check_error(unsafe {
+            LZ4F_getFrameInfo(self.c.c, &mut info, header.as_ptr(), &mut src_size)
+        })?;
+        if !matches!(info.block_mode, BlockMode::Independent) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "ParallelDecoder only decodes frames with independent blocks",
+            ));
+        }
+        if info.dict_id != 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "ParallelDecoder does not support dictionaries",
+            ));
+        }
+        self.max_block_size = info.block_size_id.get_size();
+        self.block_checksum = matches!(
+            info.block_checksum_flag,
+            BlockChecksum::BlockChecksumEnabled
+        );
+        if let ContentChecksum::ChecksumEnabled = info.content_checksum_flag {
+            self.hasher = Some(ContentHasher::new()?);
+        }
+        self.content_size = info.content_size;
+        self.stage = Stage::Blocks;
+        Ok(())
+    }
+
+    /// Reads the next block and hands it to the workers, or reads the end
+    /// mark and content checksum.
+    fn read_block(&mut self) -> Result<()> {
+        let mut field = [0u8; 4];
+        self.read_exact(&mut field)?;
+        let size = read_u32(&field);
+        let len = (size & !BLOCK_UNCOMPRESSED) as usize;
+        if len == 0 {
+            if self.hasher.is_some() {
+                self.read_exact(&mut field)?;
+                self.content_checksum = Some(read_u32(&field));
+            }
+            self.stage = Stage::Trailer;
+            return Ok(());
+        }
+        if len > self.max_block_size {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Block {} is {} bytes, more than the maximum block size",
+                    self.blocks, len
+                ),
+            ));
+        }
+        let mut data = vec![0; len];
+        self.read_exact(&mut data)?;
+        let checksum = if self.block_checksum {
+            self.read_exact(&mut field)?;
+            Some(read_u32(&field))
+        } else {
+            None
+        };
+        self.pool.submit(Job {
+            index: self.blocks,
+            data,
+            compressed: size & BLOCK_UNCOMPRESSED == 0,
+            checksum,
+            max_size: self.max_block_size,
+        })?;
+        self.blocks += 1;
+        Ok(())
+    }
+
+    /// Checks the content size and checksum once every block is decoded.
+    fn check_end(&mut self) -> Result<()> {
+        if self.content_size != 0 && self.content_size != self.decompressed {
+            return Err(LZ4Error::FrameSizeWrong.with_message(format!(
+                "Frame declares {} bytes of content, but {} bytes were decoded",
+                self.content_size, self.decompressed
+            )));
+        }
+        if let (Some(hasher), Some(expected)) = (self.hasher.as_ref(), self.content_checksum) {
+            if hasher.digest() != expected {
+                return Err(LZ4Error::ContentChecksumInvalid.into());
+            }
+        }
+        self.stage = Stage::End;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for ParallelDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            if self.pos < self.output.len() {
+                let size = cmp::min(buf.len(), self.output.len() - self.pos);
+                buf[..size].copy_from_slice(&self.output[self.pos..self.pos + size]);
+                self.pos += size;
+                return Ok(size);
+            }
+            if self.stage == Stage::Header {
+                self.read_header()?;
+            }
+            while self.stage == Stage::Blocks && self.pool.in_flight() < self.max_in_flight {
+                self.read_block()?;
+            }
+            match self.pool.next() {
+                Some(block) => {
+                    self.output = block?;
+                    self.pos = 0;
+                    if let Some(ref mut hasher) = self.hasher {
+                        hasher.update(&self.output);
+                    }
+                    self.decompressed += self.output.len() as u64;
+                }
+                None if self.stage == Stage::Trailer => self.check_end()?,
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+fn decompress_block(job: Job) -> Result<Vec<u8>> {
+    if let Some(expected) = job.checksum {
+        let actual = unsafe { XXH32(job.data.as_ptr(), job.data.len() as size_t, 0) };
+        if actual != expected {
+            return Err(LZ4Error::BlockChecksumInvalid
+                .with_message(format!("Block checksum mismatch in block {}", job.index)));
+        }
+    }
+    if !job.compressed {
+        return Ok(job.data);
+    }
+    let mut output = Vec::with_capacity(job.max_size);
+    let len = unsafe {
+        LZ4_decompress_safe(
+            job.data.as_ptr() as *const c_char,
+            output.as_mut_ptr() as *mut c_char,
+            job.data.len() as i32,
+            job.max_size as i32,
+        )
+    };
+    if len < 0 {
+        return Err(LZ4Error::DecompressionFailed
+            .with_message(format!("Failed to decompress block {}", job.index)));
+    }
+    unsafe { output.set_len(len as usize) };
+    Ok(output)
+}
+
+fn read_u32(buf: &[u8]) -> u32 {
+    u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]])
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ParallelDecoder, ParallelDecoderBuilder};
+    use crate::{
+        write_skippable_frame, BlockChecksum, BlockMode, BlockSize, ContentChecksum,
+        EncoderBuilder, ParallelEncoderBuilder,
+    };
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::io::{Cursor, Read, Write};
+
+    fn random_data(len: usize) -> Vec<u8> {
+        let mut rng = StdRng::seed_from_u64(42);
+        (0..len)
+            .map(|_| b"lz4 frame "[rng.gen_range(0, 10)])
+            .collect()
+    }
+
+    fn encode(builder: &EncoderBuilder, data: &[u8]) -> Vec<u8> {
+        let mut encoder = ParallelEncoderBuilder::new(builder)
+            .build(Vec::new())
+            .unwrap();
+        encoder.write_all(data).unwrap();
+        let (buffer, result) = encoder.finish();
+        result.unwrap();
+        buffer
+    }
+
+    #[test]
+    fn test_parallel_decoder_round_trip() {
+        let expected = random_data(2 * 1024 * 1024 + 1000);
+        let mut builder = EncoderBuilder::new();
+        builder
+            .block_mode(BlockMode::Independent)
+            .block_size(BlockSize::Max256KB)
+            .block_checksum(BlockChecksum::BlockChecksumEnabled)
+            .content_size(expected.len() as u64);
+        let mut input = Vec::new();
+        write_skippable_frame(&mut input, 3, b"metadata").unwrap();
+        input.extend_from_slice(&encode(&builder, &expected));
+        // Random bytes are stored uncompressed.
+        let mut rng = StdRng::seed_from_u64(7);
+        let noise: Vec<u8> = (0..100_000).map(|_| rng.gen()).collect();
+        let noise_frame = encode(builder.content_size(0), &noise);
+
+        for &(input, expected) in &[(&input, &expected), (&noise_frame, &noise)] {
+            let mut decoder = ParallelDecoderBuilder::new()
+                .workers(3)
+                .max_in_flight(2)
+                .build(Cursor::new(input))
+                .unwrap();
+            let mut actual = Vec::new();
+            decoder.read_to_end(&mut actual).unwrap();
+            assert!(actual == *expected);
+            let (_, result) = decoder.finish();
+            let stats = result.unwrap();
+            assert_eq!(stats.bytes_in, input.len() as u64);
+            assert_eq!(stats.bytes_out, expected.len() as u64);
+            assert_eq!(stats.frames, 1);
+        }
+    }
+
+    #[test]
+    fn test_parallel_decoder_uncompressed_end_mark() {
+        let mut builder = EncoderBuilder::new();
+        builder
+            .block_mode(BlockMode::Independent)
+            .block_checksum(BlockChecksum::BlockChecksumEnabled)
+            .checksum(ContentChecksum::ChecksumEnabled);
+        let mut input = encode(&builder, b"hello");
+        // An end mark carrying the uncompressed flag still ends the frame.
+        let end = input.len() - 8;
+        input[end..end + 4].copy_from_slice(&[0x00, 0x00, 0x00, 0x80]);
+
+        let mut decoder = ParallelDecoder::new(Cursor::new(input)).unwrap();
+        let mut actual = Vec::new();
+        decoder.read_to_end(&mut actual).unwrap();
+        assert_eq!(b"hello".to_vec(), actual);
+        let (_, result) = decoder.finish();
+        assert!(result.unwrap().content_checksum.is_some());
+    }
+
+    #[test]
+    fn test_parallel_decoder_errors() {
+        let data = random_data(300_000);
+        let mut builder = EncoderBuilder::new();
+        builder
+            .block_mode(BlockMode::Independent)
+            .block_checksum(BlockChecksum::BlockChecksumEnabled);
+        let input = encode(&builder, &data);
+
+        // Corrupt the third block (64 KB each), inside its compressed data.
+        let mut corrupted = input.clone();
+        let mut offset = 7;
+        for _ in 0..2 {
+            let size = u32::from_le_bytes([
+                corrupted[offset],
+                corrupted[offset + 1],
+                corrupted[offset + 2],
+                corrupted[offset + 3],
+            ]);
+            offset += 4 + (size & 0x7FFF_FFFF) as usize + 4;
+        }
+        corrupted[offset + 10] ^= 1;
+        let err = ParallelDecoder::new(Cursor::new(corrupted))
+            .unwrap()
+            .read_to_end(&mut Vec::new())
+            .unwrap_err();
+        assert!(err.to_string().contains("block 2"), "{}", err);
+
+        let mut truncated = ParallelDecoder::new(Cursor::new(&input[..input.len() - 1])).unwrap();
+        let err = truncated.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+        assert!(truncated.finish().1.is_err());
+
+        let mut linked = EncoderBuilder::new().build(Vec::new()).unwrap();
+        linked.write_all(&data).unwrap();
+        let (linked, result) = linked.finish();
+        result.unwrap();
+        assert!(ParallelDecoder::new(Cursor::new(linked))
+            .unwrap()
+            .read_to_end(&mut Vec::new())
+            .is_err());
+    }
+}
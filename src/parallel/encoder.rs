@@ -0,0 +1,451 @@
+use super::WorkerPool;
+use crate::encoder::{EncoderBuilder, EncoderContext, EncoderStats};
+use crate::error::Error as LZ4Error;
+use crate::frame::{ContentHasher, FrameCursor};
+use crate::liblz4::*;
+use crate::size_t;
+use std::cmp;
+use std::io::{Error, ErrorKind, Result, Write};
+use std::mem;
+use std::ptr;
+use std::sync::Arc;
+
+#[derive(Clone, Debug)]
+pub struct ParallelEncoderBuilder {
+    encoder: EncoderBuilder,
+    workers: usize,
+    // 0 == twice the number of workers
+    max_in_flight: usize,
+}
+
+#[derive(Debug)]
+pub struct ParallelEncoder<W> {
+    // Writes the header and compresses the short blocks
+    c: EncoderContext,
+    pool: WorkerPool<Arc<Vec<u8>>, Vec<u8>>,
+    w: W,
+    limit: usize,
+    auto_flush: bool,
+    max_in_flight: usize,
+    block: Vec<u8>,
+    // Last full block, and whether `c` has compressed it since it was submitted
+    last_block: Option<Arc<Vec<u8>>>,
+    replayed: bool,
+    buffer: Vec<u8>,
+    hasher: Option<ContentHasher>,
+    content_size: u64,
+    // Follows the output to count blocks
+    cursor: FrameCursor,
+    stats: EncoderStats,
+}
+
+impl ParallelEncoderBuilder {
+    /// Takes the frame settings from `encoder`, whose block mode must be
+    /// `BlockMode::Independent`. Dictionaries are not supported.
+    pub fn new(encoder: &EncoderBuilder) -> Self {
+        ParallelEncoderBuilder {
+            encoder: encoder.clone(),
+            workers: 4,
+            max_in_flight: 0,
+        }
+    }
+
+    /// Number of worker threads compressing blocks, 4 by default.
+    pub fn workers(&mut self, workers: usize) -> &mut Self {
+        self.workers = workers;
+        self
+    }
+
+    /// Maximum number of blocks submitted to the workers but not yet written,
+    /// which bounds memory use to about twice that many blocks. Defaults to
+    /// twice the number of workers.
+    pub fn max_in_flight(&mut self, blocks: usize) -> &mut Self {
+        self.max_in_flight = blocks;
+        self
+    }
+
+    pub fn build<W: Write>(&self, mut w: W) -> Result<ParallelEncoder<W>> {
+        let preferences = self.encoder.preferences();
+        let info = &preferences.frame_info;
+        if !matches!(info.block_mode, BlockMode::Independent) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "ParallelEncoder requires BlockMode::Independent",
+            ));
+        }
+        if info.dict_id != 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "ParallelEncoder does not support dictionaries",
+            ));
+        }
+        if self.workers == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "ParallelEncoder needs at least one worker",
+            ));
+        }
+        let limit = info.block_size_id.get_size();
+        let bound = check_error(unsafe { LZ4F_compressBound(limit as size_t, &preferences) })?;
+        let encoder = &self.encoder;
+        let pool = WorkerPool::new(self.workers, || {
+            // Workers only ever see full blocks and never end the frame, so
+            // the content checksum and size are left to the main thread.
+            let mut preferences = encoder.preferences();
+            preferences.frame_info.content_checksum_flag = ContentChecksum::NoChecksum;
+            preferences.frame_info.content_size = 0;
+            preferences.auto_flush = 0;
+            let c = EncoderContext::new()?;
+            let mut header = Vec::with_capacity(bound);
+            check_error(unsafe {
+                LZ4F_compressBegin(
+                    c.c,
+                    header.as_mut_ptr(),
+                    header.capacity() as size_t,
+                    &preferences,
+                )
+            })?;
+            Ok(move |block: Arc<Vec<u8>>| {
+                let mut buffer = Vec::with_capacity(bound);
+                update(&c, &mut buffer, &block)?;
+                Ok(buffer)
+            })
+        })?;
+
+        let c = EncoderContext::new()?;
+        let mut buffer = Vec::with_capacity(bound);
+        unsafe {
+            let len = check_error(LZ4F_compressBegin(
+                c.c,
+                buffer.as_mut_ptr(),
+                buffer.capacity() as size_t,
+                &preferences,
+            ))?;
+            buffer.set_len(len);
+        }
+        let mut cursor = FrameCursor::new();
+        let mut stats = EncoderStats {
+            bytes_in: 0,
+            bytes_out: 0,
+            blocks: 0,
+            header_size: buffer.len() as u64,
+            footer_size: 0,
+            content_checksum: None,
+        };
+        write_out(&mut w, &mut cursor, &mut stats, &buffer)?;
+        let hasher = match info.content_checksum_flag {
+            ContentChecksum::ChecksumEnabled => Some(ContentHasher::new()?),
+            ContentChecksum::NoChecksum => None,
+        };
+        Ok(ParallelEncoder {
+            c,
+            pool,
+            w,
+            limit,
+            auto_flush: preferences.auto_flush != 0,
+            max_in_flight: match self.max_in_flight {
+                0 => self.workers * 2,
+                blocks => blocks,
+            },
+            block: Vec::with_capacity(limit),
+            last_block: None,
+            replayed: true,
+            buffer,
+            hasher,
+            content_size: info.content_size,
+            cursor,
+            stats,
+        })
+    }
+}
+
+impl<W: Write> ParallelEncoder<W> {
+    /// Hands the current, full block to the workers, first writing out
+    /// finished blocks until fewer than `max_in_flight` are pending.
+    fn submit_block(&mut self) -> Result<()> {
+        while self.pool.in_flight() >= self.max_in_flight {
+            self.write_next()?;
+        }
+        let block = Arc::new(mem::replace(
+            &mut self.block,
+            Vec::with_capacity(self.limit),
+        ));
+        self.pool.submit(block.clone())?;
+        self.last_block = Some(block);
+        self.replayed = false;
+        Ok(())
+    }
+
+    fn write_next(&mut self) -> Result<()> {
+        match self.pool.next() {
+            Some(block) => write_out(&mut self.w, &mut self.cursor, &mut self.stats, &block?),
+            None => Ok(()),
+        }
+    }
+
+    fn write_pending(&mut self) -> Result<()> {
+        while self.pool.in_flight() > 0 {
+            self.write_next()?;
+        }
+        Ok(())
+    }
+
+    /// Compresses the current, partial block on this thread. liblz4 reuses
+    /// match tables across small independent blocks, so the context must
+    /// first see the last full block for the output to match `Encoder`'s.
+    fn write_short_block(&mut self) -> Result<()> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+        self.write_pending()?;
+        if !self.replayed {
+            if let Some(block) = self.last_block.take() {
+                update(&self.c, &mut self.buffer, &block)?;
+            }
+            self.replayed = true;
+        }
+        update(&self.c, &mut self.buffer, &self.block)?;
+        write_out(&mut self.w, &mut self.cursor, &mut self.stats, &self.buffer)?;
+        unsafe {
+            let len = check_error(LZ4F_flush(
+                self.c.c,
+                self.buffer.as_mut_ptr(),
+                self.buffer.capacity() as size_t,
+                ptr::null(),
+            ))?;
+            self.buffer.set_len(len);
+        }
+        write_out(&mut self.w, &mut self.cursor, &mut self.stats, &self.buffer)?;
+        self.block.clear();
+        Ok(())
+    }
+
+    fn write_end(&mut self) -> Result<()> {
+        self.write_short_block()?;
+        self.write_pending()?;
+        if self.content_size != 0 && self.content_size != self.stats.bytes_in {
+            return Err(LZ4Error::FrameSizeWrong.with_message(format!(
+                "Declared content size is {} bytes, but {} bytes were written",
+                self.content_size, self.stats.bytes_in
+            )));
+        }
+        let mut footer = vec![0; 4];
+        if let Some(ref hasher) = self.hasher {
+            let checksum = hasher.digest();
+            footer.extend_from_slice(&checksum.to_le_bytes());
+            self.stats.content_checksum = Some(checksum);
+        }
+        self.stats.footer_size = footer.len() as u64;
+        write_out(&mut self.w, &mut self.cursor, &mut self.stats, &footer)
+    }
+
+    /// Immutable writer reference.
+    pub fn writer(&self) -> &W {
+        &self.w
+    }
+
+    /// Sizes of the frame written so far, see `Encoder::stats()`.
+    pub fn stats(&self) -> EncoderStats {
+        self.stats.clone()
+    }
+
+    /// Compresses the remaining data, waits for the workers and writes the end
+    /// of the frame, then returns the wrapped writer along with the final
+    /// statistics, as `Encoder::finish()` does.
+    pub fn finish(mut self) -> (W, Result<EncoderStats>) {
+        let result = self.write_end().map(|()| self.stats());
+        (self.w, result)
+    }
+}
+
+impl<W: Write> Write for ParallelEncoder<W> {
+    fn write(&mut self, buffer: &[u8]) -> Result<usize> {
+        let mut offset = 0;
+        while offset < buffer.len() {
+            let size = cmp::min(buffer.len() - offset, self.limit - self.block.len());
+            let chunk = &buffer[offset..offset + size];
+            self.block.extend_from_slice(chunk);
+            if let Some(ref mut hasher) = self.hasher {
+                hasher.update(chunk);
+            }
+            offset += size;
+            self.stats.bytes_in += size as u64;
+            let result = if self.block.len() == self.limit {
+                self.submit_block()
+            } else if self.auto_flush {
+                self.write_short_block()
+            } else {
+                Ok(())
+            };
+            if let Err(e) = result {
+                // The chunk stays in the block, so it counts as taken; the
+                // error comes back on the next call.
+                return if offset > 0 { Ok(offset) } else { Err(e) };
+            }
+        }
+        Ok(offset)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.write_short_block()?;
+        self.write_pending()?;
+        self.w.flush()
+    }
+}
+
+/// Writes out compressed data and accounts for it.
+fn write_out<W: Write>(
+    w: &mut W,
+    cursor: &mut FrameCursor,
+    stats: &mut EncoderStats,
+    data: &[u8],
+) -> Result<()> {
+    w.write_all(data)?;
+    cursor.advance(data);
+    stats.bytes_out += data.len() as u64;
+    stats.blocks = cursor.block();
+    Ok(())
+}
+
+/// Feeds `data`, at most one block, to the context and leaves whatever it
+/// produced in `buffer`.
+fn update(c: &EncoderContext, buffer: &mut Vec<u8>, data: &[u8]) -> Result<()> {
+    unsafe {
+        let len = check_error(LZ4F_compressUpdate(
+            c.c,
+            buffer.as_mut_ptr(),
+            buffer.capacity() as size_t,
+            data.as_ptr(),
+            data.len() as size_t,
+            ptr::null(),
+        ))?;
+        buffer.set_len(len);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::ParallelEncoderBuilder;
+    use crate::{BlockChecksum, BlockMode, BlockSize, ContentChecksum, EncoderBuilder};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::cmp;
+    use std::io::{self, Write};
+
+    /// Takes `left` bytes, then fails.
+    struct Full {
+        left: usize,
+    }
+
+    impl Write for Full {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.left == 0 {
+                return Err(io::Error::new(io::ErrorKind::Other, "Writer is full"));
+            }
+            let len = cmp::min(self.left, buf.len());
+            self.left -= len;
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn random_data(len: usize, seed: u64) -> Vec<u8> {
+        // Compressible, but not trivially so
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..len)
+            .map(|_| b"lz4 frame "[rng.gen_range(0, 10)])
+            .collect()
+    }
+
+    /// Writes `data` in chunks of varying size, flushing after some of them.
+    fn write_chunks<W: Write>(w: &mut W, data: &[u8], flushes: bool) {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut offset = 0;
+        while offset < data.len() {
+            let size = rng.gen_range(0, 200_000).min(data.len() - offset);
+            w.write_all(&data[offset..offset + size]).unwrap();
+            if flushes && rng.gen_ratio(1, 5) {
+                w.flush().unwrap();
+            }
+            offset += size;
+        }
+    }
+
+    fn assert_identical(builder: &EncoderBuilder, data: &[u8], flushes: bool) {
+        let mut encoder = builder.build(Vec::new()).unwrap();
+        write_chunks(&mut encoder, data, flushes);
+        let (expected, result) = encoder.finish();
+        let expected_stats = result.unwrap();
+
+        let mut encoder = ParallelEncoderBuilder::new(builder)
+            .workers(3)
+            .max_in_flight(4)
+            .build(Vec::new())
+            .unwrap();
+        write_chunks(&mut encoder, data, flushes);
+        let (actual, result) = encoder.finish();
+        let stats = result.unwrap();
+        assert!(actual == expected);
+        assert_eq!(stats.bytes_in, expected_stats.bytes_in);
+        assert_eq!(stats.bytes_out, expected_stats.bytes_out);
+        assert_eq!(stats.blocks, expected_stats.blocks);
+        assert_eq!(stats.header_size, expected_stats.header_size);
+        assert_eq!(stats.footer_size, expected_stats.footer_size);
+        assert_eq!(stats.content_checksum, expected_stats.content_checksum);
+    }
+
+    #[test]
+    fn test_parallel_encoder_identical() {
+        let data = random_data(3 * 1024 * 1024 + 1234, 42);
+        for &level in &[0, 3, 9, 12] {
+            for &flushes in &[false, true] {
+                let mut builder = EncoderBuilder::new();
+                builder.block_mode(BlockMode::Independent).level(level);
+                assert_identical(&builder, &data, flushes);
+            }
+        }
+
+        let mut builder = EncoderBuilder::new();
+        builder
+            .block_mode(BlockMode::Independent)
+            .block_size(BlockSize::Max256KB)
+            .block_checksum(BlockChecksum::BlockChecksumEnabled)
+            .checksum(ContentChecksum::NoChecksum)
+            .content_size(data.len() as u64);
+        assert_identical(&builder, &data, false);
+        builder.content_size(0).auto_flush(true);
+        assert_identical(&builder, &data, true);
+        assert_identical(&builder, &data[..100], false);
+        assert_identical(&builder, &[], false);
+    }
+
+    #[test]
+    fn test_parallel_encoder_write_error() {
+        let data = random_data(3 * 64 * 1024, 42);
+        let mut builder = EncoderBuilder::new();
+        builder
+            .block_mode(BlockMode::Independent)
+            .block_size(BlockSize::Max64KB);
+        let mut encoder = ParallelEncoderBuilder::new(&builder)
+            .workers(1)
+            .max_in_flight(1)
+            .build(Full { left: 100 })
+            .unwrap();
+        // The second block waits for the first one to be written, which fails.
+        let written = encoder.write(&data).unwrap();
+        assert_eq!(written, 2 * 64 * 1024);
+        assert_eq!(encoder.stats().bytes_in, written as u64);
+        assert!(encoder.finish().1.is_err());
+    }
+
+    #[test]
+    fn test_parallel_encoder_linked() {
+        assert!(ParallelEncoderBuilder::new(&EncoderBuilder::new())
+            .build(Vec::new())
+            .is_err());
+    }
+}
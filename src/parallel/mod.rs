@@ -0,0 +1,124 @@
+//! Multithreaded compression and decompression of frames made of independent
+//! blocks. Blocks are handed to a pool of worker threads, each owning its own
+//! liblz4 context, and their results are collected in submission order.
+
+mod decoder;
+mod encoder;
+
+pub use self::decoder::{ParallelDecoder, ParallelDecoderBuilder};
+pub use self::encoder::{ParallelEncoder, ParallelEncoderBuilder};
+
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Result};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+#[derive(Debug)]
+struct WorkerPool<J, T> {
+    jobs: Option<Sender<(u64, J)>>,
+    results: Receiver<(u64, Result<T>)>,
+    threads: Vec<JoinHandle<()>>,
+    // Results that arrived ahead of an earlier job
+    ready: BTreeMap<u64, Result<T>>,
+    submitted: u64,
+    collected: u64,
+}
+
+impl<J: Send + 'static, T: Send + 'static> WorkerPool<J, T> {
+    /// Starts `workers` threads, each running jobs through its own function
+    /// created by `worker`.
+    fn new<F, G>(workers: usize, mut worker: G) -> Result<WorkerPool<J, T>>
+    where
+        F: FnMut(J) -> Result<T> + Send + 'static,
+        G: FnMut() -> Result<F>,
+    {
+        let (jobs, job_receiver) = channel::<(u64, J)>();
+        let (result_sender, results) = channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let mut threads = Vec::with_capacity(workers);
+        for _ in 0..workers {
+            let mut f = worker()?;
+            let job_receiver = job_receiver.clone();
+            let result_sender = result_sender.clone();
+            threads.push(thread::spawn(move || loop {
+                let job = match job_receiver.lock() {
+                    Ok(receiver) => receiver.recv(),
+                    Err(_) => return,
+                };
+                let (seq, job) = match job {
+                    Ok(job) => job,
+                    Err(_) => return,
+                };
+                let result =
+                    panic::catch_unwind(AssertUnwindSafe(|| f(job))).unwrap_or_else(|_| {
+                        Err(Error::new(ErrorKind::Other, "Worker thread panicked"))
+                    });
+                if result_sender.send((seq, result)).is_err() {
+                    return;
+                }
+            }));
+        }
+        Ok(WorkerPool {
+            jobs: Some(jobs),
+            results,
+            threads,
+            ready: BTreeMap::new(),
+            submitted: 0,
+            collected: 0,
+        })
+    }
+
+    /// Number of jobs submitted whose result has not been collected yet.
+    fn in_flight(&self) -> usize {
+        (self.submitted - self.collected) as usize
+    }
+
+    fn submit(&mut self, job: J) -> Result<()> {
+        let sent = match self.jobs {
+            Some(ref jobs) => jobs.send((self.submitted, job)).is_ok(),
+            None => false,
+        };
+        if !sent {
+            return Err(Error::new(ErrorKind::Other, "Worker threads have exited"));
+        }
+        self.submitted += 1;
+        Ok(())
+    }
+
+    /// Waits for the result of the oldest job in flight, or returns `None` if
+    /// there is none.
+    fn next(&mut self) -> Option<Result<T>> {
+        if self.collected == self.submitted {
+            return None;
+        }
+        loop {
+            if let Some(result) = self.ready.remove(&self.collected) {
+                self.collected += 1;
+                return Some(result);
+            }
+            match self.results.recv() {
+                Ok((seq, result)) => {
+                    self.ready.insert(seq, result);
+                }
+                Err(_) => {
+                    return Some(Err(Error::new(
+                        ErrorKind::Other,
+                        "Worker threads have exited",
+                    )))
+                }
+            }
+        }
+    }
+}
+
+impl<J, T> Drop for WorkerPool<J, T> {
+    fn drop(&mut self) {
+        // Closing the job queue lets idle workers exit.
+        self.jobs = None;
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}